@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 
+use camino::Utf8PathBuf;
 use semver::Version;
 
 #[derive(clap::Parser, Debug)]
@@ -14,19 +15,35 @@ pub enum Command {
     /// Upgrade terragrunt states or Terraform modules.
     Upgrade(UpgradeArgs),
     /// Given a PR, run terragrunt/terraform plan on every module that changed.
+    /// The PR is checked out in a temporary git worktree, so your local checkout isn't touched.
     PlanPr(PlanPr),
+    /// Run terragrunt/terraform plan on every module that changed in the current branch,
+    /// without the need of a PR.
+    PlanBranch(PlanBranch),
     /// Select a provider and upgrade all lockfiles.
-    UpgradeProvider,
+    UpgradeProvider(UpgradeProviderArgs),
     /// Create default configuration and print its path.
     /// If you are using 1Password, you can get an `ITEM_ID` by running
     /// `op item list`.
     Config,
     /// Login to the AWS legacy account.
-    /// TODO: Doesn't work yet because it doesn't export the env vars.
+    /// Print the commands that export the credentials, e.g. `eval "$(infratk legacy-login)"`,
+    /// or start a shell with the credentials.
     #[command(visible_alias = "ll")]
-    LegacyLogin,
+    LegacyLogin(LegacyLoginArgs),
     /// Get the graph of the terraform modules to see how they depend on each other.
     Graph(GraphArgs),
+    /// Explain why a module depends on another one, by printing the shortest
+    /// chain of dependencies between them.
+    Why(WhyArgs),
+    /// Check the repository for issues. Exits with a non-zero code if any issue is found.
+    Lint(LintArgs),
+    /// Print the order in which modules should be applied, grouped in waves.
+    /// Modules of a wave only depend on modules of the previous waves.
+    Order(OrderArgs),
+    /// Run a command with the credentials of an account.
+    /// E.g. `infratk exec crates-io -- aws s3 ls`.
+    Exec(ExecArgs),
 }
 
 #[derive(clap::Parser, Debug)]
@@ -35,18 +52,122 @@ pub struct UpgradeArgs {
     /// detected by git as untracked changes.
     #[arg(long)]
     pub git: bool,
+    /// With `--git`, use the files changed in the commits of the current branch since
+    /// it diverged from this ref (e.g. `main`), instead of the uncommitted changes.
+    #[arg(long, requires = "git", conflicts_with_all = ["staged", "commit"])]
+    pub since: Option<String>,
+    /// With `--git`, use the staged files instead of the uncommitted changes.
+    #[arg(long, requires = "git", conflicts_with = "commit")]
+    pub staged: bool,
+    /// With `--git`, use the files changed in this commit instead of the uncommitted changes.
+    #[arg(long, requires = "git")]
+    pub commit: Option<String>,
     /// If true, copy the output to the clipboard.
     #[arg(long)]
     pub clipboard: bool,
+    /// If true, explain why each module is upgraded when using `--git`.
+    #[arg(long, short)]
+    pub verbose: bool,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct UpgradeProviderArgs {
+    /// If true, commit the lockfiles of the modules without changes in a new branch,
+    /// push it to `origin` and open a PR.
+    /// The providers are upgraded in the default branch of the repository, checked out in a
+    /// separate worktree, so that the current branch isn't touched.
+    #[arg(long, conflicts_with = "batch")]
+    pub open_pr: bool,
+    /// If true, don't select providers interactively. Instead, for every outdated provider,
+    /// upgrade only that provider starting from the default branch of the repository and open a
+    /// separate PR. Like `--open-pr`, the current branch isn't touched.
+    #[arg(long)]
+    pub batch: bool,
 }
 
 #[derive(clap::Parser, Debug)]
 pub struct PlanPr {
     /// PR Number OR URL OR Branch.
+    /// Branches of forks are prefixed with the owner, e.g. `owner:branch`.
     pub pr: String,
     /// If true, copy the output to the clipboard.
     #[arg(long)]
     pub clipboard: bool,
+    /// If true, post the plan summary as a PR comment.
+    /// If infratk already commented on the PR, the comment is updated.
+    #[arg(long)]
+    pub comment: bool,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct PlanBranch {
+    /// Branch or commit the current branch is based on. E.g. `main`.
+    #[arg(long)]
+    pub base: String,
+    /// If true, include uncommitted changes.
+    #[arg(long)]
+    pub uncommitted: bool,
+    /// If true, copy the output to the clipboard.
+    #[arg(long)]
+    pub clipboard: bool,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct LegacyLoginArgs {
+    /// Shell of the printed commands or of the started shell.
+    /// Defaults to the shell of the `SHELL` environment variable.
+    #[arg(long, value_enum)]
+    pub shell: Option<Shell>,
+    /// If true, start a new shell with the credentials instead of printing them.
+    #[arg(long)]
+    pub subshell: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct ExecArgs {
+    /// Account directory under `terragrunt/accounts`, e.g. `legacy` or `root`.
+    pub account: String,
+    /// Command to run, with its arguments.
+    #[arg(last = true, required = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct WhyArgs {
+    /// Module (or file of the module) that is a dependency. E.g. a changed file.
+    pub from: Utf8PathBuf,
+    /// Module that depends on `from`. E.g. a terragrunt stack.
+    pub to: Utf8PathBuf,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct OrderArgs {
+    /// Modules to sort. If empty, all the modules containing a lockfile are sorted.
+    pub modules: Vec<Utf8PathBuf>,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct LintArgs {
+    #[command(subcommand)]
+    pub command: LintCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum LintCommand {
+    /// Check the graph of modules for missing dependencies, dependencies outside
+    /// of the repository and dependency cycles.
+    Graph,
+    /// Check for terraform modules that aren't used by any stack, stacks without
+    /// terraform or terragrunt files and lockfiles in unknown directories.
+    DeadModules,
 }
 
 #[derive(clap::Parser, Debug)]
@@ -54,6 +175,10 @@ pub struct GraphArgs {
     /// If true, copy the graphviz output to the clipboard.
     #[arg(long)]
     pub clipboard: bool,
+    /// Write an interactive HTML page with the graph to this file,
+    /// instead of printing the graphviz output.
+    #[arg(long)]
+    pub html: Option<Utf8PathBuf>,
     /// Check for outdated providers and show them in the graph.
     #[arg(long)]
     pub outdated: bool,
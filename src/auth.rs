@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context as _;
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::{DateTime, Duration, Utc};
+use secrecy::SecretString;
+
+use crate::{
+    aws,
+    cmd::Cmd,
+    config::{AccountAuth, Config},
+    secret::SecretSource,
+};
+
+/// Credentials are refreshed if they expire in less than this time,
+/// so that they don't expire while running terraform.
+pub const MIN_VALIDITY_MINUTES: i64 = 15;
+
+/// Credentials of an account.
+pub struct Session {
+    /// Environment variables that you need to use to authenticate with the account.
+    pub env_vars: BTreeMap<String, SecretString>,
+    /// When the credentials expire, if known.
+    pub expiration: Option<DateTime<Utc>>,
+}
+
+impl Session {
+    /// Whether the credentials expire in less than [`MIN_VALIDITY_MINUTES`] from `now`.
+    pub fn expires_soon(&self, now: DateTime<Utc>) -> bool {
+        self.expiration
+            .is_some_and(|expiration| expiration - now < Duration::minutes(MIN_VALIDITY_MINUTES))
+    }
+}
+
+/// Way to get the credentials of an account.
+pub trait AuthProvider {
+    /// If `reuse_cache` is true, cached credentials that don't expire soon are reused.
+    fn login(&self, reuse_cache: bool) -> Session;
+}
+
+/// Login with the authentication method of an account.
+pub fn login(auth: &AccountAuth, config: &Config, reuse_cache: bool) -> Session {
+    provider(auth, config).login(reuse_cache)
+}
+
+fn provider<'a>(auth: &'a AccountAuth, config: &'a Config) -> Box<dyn AuthProvider + 'a> {
+    match auth {
+        AccountAuth::Sso { .. } | AccountAuth::Legacy | AccountAuth::AssumeRole { .. } => {
+            Box::new(Aws { auth, config })
+        }
+        AccountAuth::GcpAdc {
+            credentials_file,
+            project,
+        } => Box::new(GcpAdc {
+            credentials_file: credentials_file.as_deref(),
+            project: project.as_deref(),
+        }),
+        AccountAuth::EnvFile { path } => Box::new(EnvFile { path }),
+        AccountAuth::Secrets { env } => Box::new(Secrets { env }),
+        AccountAuth::Skip => panic!("can't login to an account configured to be skipped"),
+    }
+}
+
+struct Aws<'a> {
+    auth: &'a AccountAuth,
+    config: &'a Config,
+}
+
+impl AuthProvider for Aws<'_> {
+    fn login(&self, reuse_cache: bool) -> Session {
+        aws::login(self.auth, self.config, reuse_cache)
+    }
+}
+
+struct GcpAdc<'a> {
+    credentials_file: Option<&'a Utf8Path>,
+    project: Option<&'a str>,
+}
+
+impl AuthProvider for GcpAdc<'_> {
+    fn login(&self, reuse_cache: bool) -> Session {
+        let mut env_vars = BTreeMap::new();
+        match self.credentials_file {
+            Some(credentials_file) => {
+                let credentials_file = expand_home(credentials_file);
+                assert!(
+                    credentials_file.exists(),
+                    "GCP credentials file {credentials_file} not found"
+                );
+                env_vars.insert(
+                    "GOOGLE_APPLICATION_CREDENTIALS".to_string(),
+                    credentials_file.to_string().into(),
+                );
+            }
+            None => {
+                // gcloud refreshes the access token of the default credentials by itself,
+                // so login only if they are missing or were rejected.
+                if !reuse_cache || !default_gcp_credentials().exists() {
+                    let output =
+                        Cmd::new("gcloud", ["auth", "application-default", "login"]).run();
+                    assert!(output.status().success());
+                }
+            }
+        }
+        if let Some(project) = self.project {
+            env_vars.insert("GOOGLE_CLOUD_PROJECT".to_string(), project.to_string().into());
+        }
+        Session {
+            env_vars,
+            expiration: None,
+        }
+    }
+}
+
+/// File written by `gcloud auth application-default login`.
+fn default_gcp_credentials() -> Utf8PathBuf {
+    let gcloud_dir = match std::env::var("CLOUDSDK_CONFIG") {
+        Ok(dir) => Utf8PathBuf::from(dir),
+        Err(_) => expand_home(Utf8Path::new("~/.config/gcloud")),
+    };
+    gcloud_dir.join("application_default_credentials.json")
+}
+
+struct EnvFile<'a> {
+    path: &'a Utf8Path,
+}
+
+impl AuthProvider for EnvFile<'_> {
+    fn login(&self, _reuse_cache: bool) -> Session {
+        let path = expand_home(self.path);
+        let content = fs_err::read_to_string(&path).unwrap();
+        let env_vars = parse_env_file(&content)
+            .with_context(|| format!("invalid env file {path}"))
+            .unwrap();
+        Session {
+            env_vars,
+            expiration: None,
+        }
+    }
+}
+
+struct Secrets<'a> {
+    env: &'a BTreeMap<String, SecretSource>,
+}
+
+impl AuthProvider for Secrets<'_> {
+    fn login(&self, _reuse_cache: bool) -> Session {
+        let env_vars = self
+            .env
+            .iter()
+            .map(|(key, source)| {
+                let value = source
+                    .backend()
+                    .get()
+                    .with_context(|| format!("failed to get the value of {key}"))
+                    .unwrap();
+                (key.clone(), value)
+            })
+            .collect();
+        Session {
+            env_vars,
+            expiration: None,
+        }
+    }
+}
+
+/// Parse `KEY=value` lines, ignoring empty lines and comments.
+/// Lines can start with `export` and values can be quoted.
+pub fn parse_env_file(content: &str) -> anyhow::Result<BTreeMap<String, SecretString>> {
+    let mut env_vars = BTreeMap::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .with_context(|| format!("line without `=`: {line}"))?;
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+            .unwrap_or(value);
+        env_vars.insert(key.trim().to_string(), value.to_string().into());
+    }
+    Ok(env_vars)
+}
+
+fn expand_home(path: &Utf8Path) -> Utf8PathBuf {
+    match path.strip_prefix("~") {
+        Ok(relative) => {
+            let home_dir = Utf8PathBuf::from_path_buf(home::home_dir().unwrap()).unwrap();
+            home_dir.join(relative)
+        }
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::ExposeSecret as _;
+
+    use super::*;
+
+    #[test]
+    fn env_file_is_parsed() {
+        let content = r#"
+# Fastly
+export FASTLY_API_KEY="fastly-key"
+DD_API_KEY='datadog key'
+DD_SITE = datadoghq.eu
+"#;
+        let env_vars = parse_env_file(content).unwrap();
+        let env_vars: Vec<(&str, &str)> = env_vars
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.expose_secret().as_str()))
+            .collect();
+        assert_eq!(
+            env_vars,
+            vec![
+                ("DD_API_KEY", "datadog key"),
+                ("DD_SITE", "datadoghq.eu"),
+                ("FASTLY_API_KEY", "fastly-key"),
+            ]
+        );
+    }
+}
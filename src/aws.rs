@@ -1,65 +1,209 @@
 use std::collections::BTreeMap;
 
+use chrono::{DateTime, Utc};
 use secrecy::SecretString;
+use serde::Deserialize;
 
-use crate::{cmd::Cmd, config::Config, git};
+use crate::{
+    auth::{self, Session},
+    aws_config::{aws_dir, profile_section, section_value},
+    cmd::Cmd,
+    config::{AccountAuth, Config},
+    secret::SecretSource,
+    sso_cache,
+    sts::{self, AccessKey, StsClient},
+};
 
-/// Returns a map of environment variables that you need to use to authenticate with the account.
+/// Login to an AWS account.
+/// If `reuse_cache` is true, cached credentials that don't expire soon are reused.
 #[must_use]
-pub fn login(account_dir: &str, config: &Config) -> BTreeMap<String, SecretString> {
-    match account_dir {
-        "legacy" => legacy_login(config.op_legacy_item_id.as_deref()),
-        _ => {
-            sso_login(account_dir);
-            BTreeMap::new()
+pub fn login(auth: &AccountAuth, config: &Config, reuse_cache: bool) -> Session {
+    login_with_chain(auth, config, reuse_cache, &mut vec![])
+}
+
+/// `chain` contains the source accounts of the assume-role chain that are being logged in.
+fn login_with_chain(
+    auth: &AccountAuth,
+    config: &Config,
+    reuse_cache: bool,
+    chain: &mut Vec<String>,
+) -> Session {
+    match auth {
+        AccountAuth::Sso { profile } => sso_login(profile, reuse_cache),
+        AccountAuth::Legacy => legacy_login(config, reuse_cache),
+        AccountAuth::AssumeRole { source, role_arns } => {
+            assert!(
+                !chain.contains(source),
+                "cycle in the assume-role chain of account {source}: {chain:?}"
+            );
+            chain.push(source.clone());
+            let source_auth = config.account_auth(source);
+            let mut session = login_with_chain(&source_auth, config, reuse_cache, chain);
+            // The session of the last role is the only one used by the commands,
+            // so its expiration is the one that matters.
+            for role_arn in role_arns {
+                session = assume_role(role_arn, session.env_vars);
+            }
+            session
         }
+        auth => panic!("{auth:?} isn't an AWS authentication"),
     }
 }
 
-/// Returns a map of environment variables that can be used to authenticate with the legacy account.
-pub fn legacy_login(op_legacy_item_id: Option<&str>) -> BTreeMap<String, SecretString> {
-    let repo = git::repo();
-    let git_root = git::git_root(&repo);
-    let mut env_vars = BTreeMap::new();
-    let mut cred_cmd = Cmd::new("python3", ["./aws-creds.py"]);
-    cred_cmd.hide_stdout().with_current_dir(git_root);
-    if let Some(op_legacy_item_id) = op_legacy_item_id {
-        let totp_code_output = Cmd::new("op", ["item", "get", op_legacy_item_id, "--otp"]).run();
-        assert!(totp_code_output.status().success());
-        let totp_code = totp_code_output.stdout().trim().to_string();
-        cred_cmd.with_env_vars([("TOTP_CODE".to_string(), totp_code.into())].into());
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AssumeRoleOutput {
+    credentials: Credentials,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: SecretString,
+    session_token: SecretString,
+    expiration: DateTime<Utc>,
+}
+
+/// Assume `role_arn` with the credentials of `env_vars`.
+fn assume_role(role_arn: &str, env_vars: BTreeMap<String, SecretString>) -> Session {
+    let output = Cmd::new(
+        "aws",
+        [
+            "sts",
+            "assume-role",
+            "--role-arn",
+            role_arn,
+            "--role-session-name",
+            "infratk",
+            "--output",
+            "json",
+        ],
+    )
+    .with_env_vars(env_vars)
+    .hide_stdout()
+    .run();
+    assert!(output.status().success(), "failed to assume role {role_arn}");
+    let output: AssumeRoleOutput = serde_json::from_str(output.stdout()).unwrap();
+    let credentials = output.credentials;
+    let env_vars = BTreeMap::from([
+        (
+            "AWS_ACCESS_KEY_ID".to_string(),
+            credentials.access_key_id.into(),
+        ),
+        (
+            "AWS_SECRET_ACCESS_KEY".to_string(),
+            credentials.secret_access_key,
+        ),
+        ("AWS_SESSION_TOKEN".to_string(), credentials.session_token),
+    ]);
+    Session {
+        env_vars,
+        expiration: Some(credentials.expiration),
     }
-    let outcome = cred_cmd.run();
-    assert!(
-        outcome.status().success(),
-        "failed to login to legacy account"
-    );
-    for line in outcome.stdout().lines() {
-        if line.contains("export") {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            let key = parts[1].split('=').next().unwrap();
-            let value = parts[1].split('=').last().unwrap().trim_matches('"');
+}
 
-            env_vars.insert(key.to_string(), SecretString::new(value.to_string()));
+/// Login to the legacy account.
+/// The session credentials are cached until they expire.
+/// If `reuse_cache` is false, new credentials are requested even if the cached ones are valid.
+pub fn legacy_login(config: &Config, reuse_cache: bool) -> Session {
+    let login_config = &config.legacy_login;
+    if let Some(credentials_command) = &login_config.credentials_command {
+        return credentials_command_login(credentials_command);
+    }
+    let profile = login_config.profile();
+    if reuse_cache {
+        if let Some(credentials) = sts::load_cached_session(profile) {
+            eprintln!(
+                "ℹ️ Reusing the session of the legacy account, valid until {}",
+                credentials.expiration
+            );
+            return credentials.session();
         }
     }
-    env_vars
+    let credentials_file = read_aws_file("credentials");
+    let access_key = AccessKey {
+        id: section_value(&credentials_file, profile, "aws_access_key_id")
+            .unwrap_or_else(|| panic!("aws_access_key_id of profile {profile} not found")),
+        secret: section_value(&credentials_file, profile, "aws_secret_access_key")
+            .unwrap_or_else(|| panic!("aws_secret_access_key of profile {profile} not found"))
+            .into(),
+    };
+    let mfa_serial = login_config
+        .mfa_serial
+        .clone()
+        .or_else(|| {
+            section_value(&read_aws_file("config"), &profile_section(profile), "mfa_serial")
+        })
+        .or_else(|| section_value(&credentials_file, profile, "mfa_serial"))
+        .unwrap_or_else(|| panic!("mfa_serial of profile {profile} not found"));
+    let mfa_source = config.legacy_mfa().unwrap_or(SecretSource::Prompt {
+        message: Some("MFA code of the legacy account:".to_string()),
+    });
+    let totp_code = mfa_source
+        .backend()
+        .get()
+        .expect("failed to get the MFA code of the legacy account");
+    let client = StsClient::new(
+        login_config.sts_endpoint().to_string(),
+        login_config.sts_region().to_string(),
+        access_key,
+    );
+    let credentials = client
+        .get_session_token(&mfa_serial, &totp_code, login_config.duration_seconds())
+        .expect("failed to login to legacy account");
+    sts::save_session(profile, &credentials);
+    credentials.session()
 }
 
-pub fn sso_login(account_dir: &str) {
-    assert_ne!(
-        account_dir, "legacy",
-        "can't login to legacy account with sso"
+/// Run the command and read the credentials from the `export KEY=value` lines it prints.
+/// Other lines are ignored.
+fn credentials_command_login(credentials_command: &[String]) -> Session {
+    let (program, args) = credentials_command
+        .split_first()
+        .expect("credentials_command of the legacy account is empty");
+    let output = Cmd::new(program, args).hide_stdout().run();
+    assert!(
+        output.status().success(),
+        "credentials_command of the legacy account failed"
     );
-    let account = match account_dir {
-        "root" => "rust-root",
-        account_dir => account_dir,
-    };
-    let output = Cmd::new("aws", ["sso", "login", "--profile", account]).run();
-    assert!(output.status().success());
+    let exports = output
+        .stdout()
+        .lines()
+        .filter(|line| line.trim_start().starts_with("export "))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let env_vars = auth::parse_env_file(&exports)
+        .expect("invalid output of the credentials_command of the legacy account");
+    Session {
+        env_vars,
+        expiration: None,
+    }
 }
 
-pub fn sso_logout() {
-    let output = Cmd::new("aws", ["sso", "logout"]).run();
+/// Content of a file under `~/.aws`, or an empty string if it doesn't exist.
+fn read_aws_file(name: &str) -> String {
+    aws_dir()
+        .and_then(|dir| fs_err::read_to_string(dir.join(name)).ok())
+        .unwrap_or_default()
+}
+
+/// The environment variables of the session select the profile.
+/// If `reuse_cache` is true, the login is skipped if the SSO session of the profile is still valid.
+pub fn sso_login(profile: &str, reuse_cache: bool) -> Session {
+    let env_vars = BTreeMap::from([("AWS_PROFILE".to_string(), profile.to_string().into())]);
+    let session = Session {
+        env_vars,
+        expiration: sso_cache::session_expiration(profile),
+    };
+    if reuse_cache && session.expiration.is_some() && !session.expires_soon(Utc::now()) {
+        eprintln!("ℹ️ Reusing the SSO session of profile {profile}");
+        return session;
+    }
+    let output = Cmd::new("aws", ["sso", "login", "--profile", profile]).run();
     assert!(output.status().success());
+    Session {
+        expiration: sso_cache::session_expiration(profile),
+        ..session
+    }
 }
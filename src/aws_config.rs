@@ -0,0 +1,34 @@
+use camino::Utf8PathBuf;
+
+/// Directory of the files of the AWS CLI, e.g. `~/.aws/config`.
+pub fn aws_dir() -> Option<Utf8PathBuf> {
+    let home_dir = Utf8PathBuf::from_path_buf(home::home_dir()?).ok()?;
+    Some(home_dir.join(".aws"))
+}
+
+/// Section of `profile` in `~/.aws/config`.
+/// In `~/.aws/credentials`, the section is just the profile name.
+pub fn profile_section(profile: &str) -> String {
+    if profile == "default" {
+        "default".to_string()
+    } else {
+        format!("profile {profile}")
+    }
+}
+
+/// Value of `key` in the `[section]` of an INI file.
+pub fn section_value(ini: &str, section: &str, key: &str) -> Option<String> {
+    let mut in_section = false;
+    for line in ini.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_section = name.trim() == section;
+        } else if in_section {
+            if let Some((k, v)) = line.split_once('=') {
+                if k.trim() == key {
+                    return Some(v.trim().to_string());
+                }
+            }
+        }
+    }
+    None
+}
@@ -1,10 +1,9 @@
 use arboard::Clipboard;
-use regex::Regex;
+
+use crate::pretty_format;
 
 pub fn copy_to_clipboard(output_str: String) {
-    // Strip ANSI escape sequences
-    let re = Regex::new(r"\x1b\[([\x30-\x3f]*[\x20-\x2f]*[\x40-\x7e])").unwrap();
-    let output_str = re.replace_all(&output_str, "").to_string();
+    let output_str = pretty_format::strip_ansi_escapes(&output_str);
 
     let mut clipboard = Clipboard::new().unwrap();
     clipboard.set_text(output_str).unwrap();
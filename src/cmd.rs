@@ -30,6 +30,34 @@ impl CmdOutput {
     }
 }
 
+/// Environment variables inherited from the user's shell that select the AWS credentials.
+/// They are removed from the environment of the commands, so that only the credentials
+/// of the account passed with [`Cmd::with_env_vars`] are used.
+pub const AWS_CREDENTIALS_ENV_VARS: &[&str] = &[
+    "AWS_ACCESS_KEY_ID",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+    "AWS_SECURITY_TOKEN",
+    "AWS_CREDENTIAL_EXPIRATION",
+    "AWS_PROFILE",
+    "AWS_DEFAULT_PROFILE",
+    "AWS_ROLE_ARN",
+    "AWS_ROLE_SESSION_NAME",
+    "AWS_WEB_IDENTITY_TOKEN_FILE",
+    "AWS_CONTAINER_CREDENTIALS_RELATIVE_URI",
+    "AWS_CONTAINER_CREDENTIALS_FULL_URI",
+    "AWS_CONTAINER_AUTHORIZATION_TOKEN",
+];
+
+/// Environment variables whose value isn't masked when printing the command.
+const NON_SECRET_ENV_VARS: &[&str] = &[
+    "AWS_PROFILE",
+    "AWS_REGION",
+    "AWS_DEFAULT_REGION",
+    "GOOGLE_APPLICATION_CREDENTIALS",
+    "GOOGLE_CLOUD_PROJECT",
+];
+
 pub struct Cmd {
     name: String,
     env_vars: BTreeMap<String, SecretString>,
@@ -72,17 +100,9 @@ impl Cmd {
         self
     }
 
+    /// Run the command, capturing its output while printing it.
     pub fn run(&self) -> CmdOutput {
-        let mut to_print = format!("🚀 {} {}", self.name, self.args.join(" "));
-        let mut command = Command::new(&self.name);
-        if let Some(dir) = &self.current_dir {
-            command.current_dir(dir);
-            to_print.push_str(&format!(" 👉 {}", dir));
-        }
-        for (key, value) in &self.env_vars {
-            command.env(key, value.expose_secret());
-        }
-        println!("{to_print}");
+        let mut command = self.command();
         let mut child = command
             .args(&self.args)
             .stdout(Stdio::piped())
@@ -137,4 +157,58 @@ impl Cmd {
             stderr: output_stderr,
         }
     }
+
+    /// Run the command attached to the terminal, e.g. because it's interactive.
+    /// The output isn't captured.
+    pub fn run_interactive(&self) -> ExitStatus {
+        self.command().args(&self.args).status().unwrap()
+    }
+
+    /// Print the command and prepare it, with the environment variables of the account.
+    fn command(&self) -> Command {
+        let command_line = format!("{} {}", self.name, self.args.join(" "));
+        let mut to_print = format!("🚀 {}", mask_secrets(&command_line, &self.env_vars));
+        let mut command = Command::new(&self.name);
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+            to_print.push_str(&format!(" 👉 {}", dir));
+        }
+        for key in AWS_CREDENTIALS_ENV_VARS {
+            command.env_remove(key);
+        }
+        for (key, value) in &self.env_vars {
+            command.env(key, value.expose_secret());
+        }
+        // Print to stderr, so that the stdout of infratk can be used by other programs.
+        eprintln!("{to_print}");
+        command
+    }
+}
+
+/// Replace the values of the secret environment variables contained in `text`,
+/// e.g. because the user passed `$AWS_SECRET_ACCESS_KEY` as an argument.
+fn mask_secrets(text: &str, env_vars: &BTreeMap<String, SecretString>) -> String {
+    env_vars
+        .iter()
+        .filter(|(key, _)| !NON_SECRET_ENV_VARS.contains(&key.as_str()))
+        .map(|(_, value)| value.expose_secret())
+        .filter(|value| !value.is_empty())
+        .fold(text.to_string(), |text, value| text.replace(value, "****"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_masked() {
+        let env_vars = BTreeMap::from([
+            ("AWS_PROFILE".to_string(), SecretString::new("root".to_string())),
+            ("AWS_SESSION_TOKEN".to_string(), SecretString::new("t0ken".to_string())),
+        ]);
+        assert_eq!(
+            mask_secrets("aws sts --token t0ken --profile root", &env_vars),
+            "aws sts --token **** --profile root"
+        );
+    }
 }
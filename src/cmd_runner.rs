@@ -1,23 +1,83 @@
-use std::collections::BTreeMap;
+use std::cell::RefCell;
 
 use camino::Utf8Path;
-use secrecy::SecretString;
+use chrono::Utc;
 
-use crate::cmd::Cmd;
+use crate::{
+    auth::Session,
+    cmd::{Cmd, CmdOutput},
+    lockfile, LOCKFILE,
+};
 
-#[derive(Debug, PartialEq)]
+/// Parts of the errors returned when the credentials are expired or invalid.
+const AUTH_ERRORS: &[&str] = &[
+    "expiredtoken",
+    "invalidclienttokenid",
+    "unrecognizedclientexception",
+    "the security token included in the request is expired",
+    "the security token included in the request is invalid",
+    "token has expired",
+    "sso session associated with this profile has expired",
+    "no valid credential sources found",
+    // Google Cloud application default credentials.
+    "invalid_grant",
+    "invalid_rapt",
+    "reauthentication is needed",
+    "could not find default credentials",
+    "request had invalid authentication credentials",
+];
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum PlanOutcome {
     NoChanges,
     Changes(String),
+    /// The credentials were rejected, even after logging in again.
+    AuthFailed(String),
+}
+
+/// Why a stack is planned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanReason {
+    /// Files of the stack changed.
+    Changed,
+    /// The stack depends on a module that changed.
+    Dependent,
+    /// The stack was deleted, so its destruction is planned.
+    Removed,
 }
 
-pub struct CmdRunner {
-    env_vars: BTreeMap<String, SecretString>,
+pub struct CmdRunner<'a> {
+    session: RefCell<Session>,
+    /// Login again. The argument tells whether cached credentials can be reused.
+    login: Box<dyn Fn(bool) -> Session + 'a>,
 }
 
-impl CmdRunner {
-    pub fn new(env_vars: BTreeMap<String, SecretString>) -> Self {
-        Self { env_vars }
+impl<'a> CmdRunner<'a> {
+    /// Login with `login` and run the commands with the returned credentials.
+    /// `login` is called again if the credentials are about to expire or are rejected.
+    /// Its argument tells whether cached credentials can be reused.
+    pub fn new(login: impl Fn(bool) -> Session + 'a) -> Self {
+        let session = login(true);
+        Self {
+            session: RefCell::new(session),
+            login: Box::new(login),
+        }
+    }
+
+    /// Run the command with the credentials of the session.
+    /// If the credentials are rejected, login again and retry once.
+    fn run(&self, cmd: &mut Cmd) -> CmdOutput {
+        if self.session.borrow().expires_soon(Utc::now()) {
+            eprintln!("🔑 The credentials are about to expire: logging in again");
+            self.session.replace((self.login)(true));
+        }
+        let output = cmd.with_env_vars(self.session.borrow().env_vars.clone()).run();
+        if output.status().success() || !is_auth_error(output.stderr()) {
+            return output;
+        }
+        eprintln!("🔑 The credentials were rejected: logging in again");
+        self.session.replace((self.login)(false));
+        cmd.with_env_vars(self.session.borrow().env_vars.clone()).run()
     }
 
     pub fn terragrunt_plan(&self, state: &Utf8Path) -> PlanOutcome {
@@ -28,20 +88,35 @@ impl CmdRunner {
         self.plan(module, "terraform")
     }
 
+    /// Plan the destruction of all the resources of the state.
+    pub fn terragrunt_plan_destroy(&self, state: &Utf8Path) -> PlanOutcome {
+        self.plan_with_args(state, "terragrunt", &["-destroy"])
+    }
+
+    /// Plan the destruction of all the resources of the module.
+    pub fn terraform_plan_destroy(&self, module: &Utf8Path) -> PlanOutcome {
+        self.plan_with_args(module, "terraform", &["-destroy"])
+    }
+
     /// Check if Terragrunt or Terraform plan is clean.
     /// Useful to check wheter there are some unapplied changes in the repo.
     fn plan(&self, directory: &Utf8Path, command: &str) -> PlanOutcome {
+        self.plan_with_args(directory, command, &[])
+    }
+
+    fn plan_with_args(&self, directory: &Utf8Path, command: &str, extra_args: &[&str]) -> PlanOutcome {
         // The `-detailed-exitcode` returns the following exit codes:
         // 0 - Succeeded, diff is empty (no changes)
         // 1 - Errored
         // 2 - Succeeded, there is a diff
-        let output = Cmd::new(command, ["plan", "-detailed-exitcode", "-input=false"])
-            .with_env_vars(self.env_vars.clone())
-            .with_current_dir(directory)
-            .run();
+        let mut args = vec!["plan", "-detailed-exitcode", "-input=false"];
+        args.extend(extra_args);
+        let output = self.run(Cmd::new(command, args).with_current_dir(directory));
         let is_diff_empty = output.status().code().unwrap() == 0;
         if is_diff_empty {
             PlanOutcome::NoChanges
+        } else if output.status().code().unwrap() == 1 && is_auth_error(output.stderr()) {
+            PlanOutcome::AuthFailed(output.stderr().to_string())
         } else {
             let plan_details = output.stdout().split("Terraform will perform the following actions:").last().expect("Terraform output did not contain 'Terraform will perform the following actions:'");
             let mut plan_details = match plan_details
@@ -61,18 +136,74 @@ impl CmdRunner {
         }
     }
 
+    pub fn terraform_init(&self, directory: &Utf8Path) {
+        self.init(directory, "terraform")
+    }
+
+    fn init(&self, directory: &Utf8Path, command: &str) {
+        self.run(Cmd::new(command, ["init", "-input=false"]).with_current_dir(directory));
+    }
+
+    /// Upgrade the lockfile.
+    /// If `only_provider` is set, the other providers of the lockfile keep their version.
+    pub fn terragrunt_upgrade(&self, directory: &Utf8Path, only_provider: Option<&str>) {
+        self.upgrade(directory, "terragrunt", only_provider)
+    }
+
+    /// Upgrade the lockfile.
+    /// If `only_provider` is set, the other providers of the lockfile keep their version.
+    pub fn terraform_upgrade(&self, directory: &Utf8Path, only_provider: Option<&str>) {
+        self.upgrade(directory, "terraform", only_provider)
+    }
+
+    fn upgrade(&self, directory: &Utf8Path, command: &str, only_provider: Option<&str>) {
+        let Some(provider) = only_provider else {
+            return self.init_upgrade(directory, command);
+        };
+        // `init --upgrade` upgrades all the providers, so restore the others afterwards.
+        let lockfile_path = directory.join(LOCKFILE);
+        let original = fs_err::read_to_string(&lockfile_path).unwrap();
+        self.init_upgrade(directory, command);
+        let upgraded = fs_err::read_to_string(&lockfile_path).unwrap();
+        let lockfile = lockfile::keep_only_provider_upgrade(&original, &upgraded, provider);
+        fs_err::write(&lockfile_path, lockfile).unwrap();
+        // Install the provider versions of the lockfile.
+        self.init(directory, command);
+    }
+
     pub fn terragrunt_init_upgrade(&self, directory: &Utf8Path) {
         self.init_upgrade(directory, "terragrunt")
     }
 
-    pub fn terraform_init_upgrade(&self, directory: &Utf8Path) {
-        self.init_upgrade(directory, "terraform")
+    fn init_upgrade(&self, directory: &Utf8Path, command: &str) {
+        self.run(
+            Cmd::new(command, ["init", "--upgrade", "-input=false"]).with_current_dir(directory),
+        );
     }
+}
 
-    fn init_upgrade(&self, directory: &Utf8Path, command: &str) {
-        Cmd::new(command, ["init", "--upgrade", "-input=false"])
-            .with_env_vars(self.env_vars.clone())
-            .with_current_dir(directory)
-            .run();
+fn is_auth_error(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    AUTH_ERRORS.iter().any(|error| stderr.contains(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auth_errors_are_recognized() {
+        assert!(is_auth_error(
+            "Error: error configuring Terraform AWS Provider: ExpiredToken: The security token included in the request is expired"
+        ));
+        assert!(is_auth_error(
+            "Error when retrieving token from sso: Token has expired and refresh failed"
+        ));
+        assert!(is_auth_error(
+            "Error: Attempted to load application default credentials since neither `credentials` nor `access_token` was set in the provider block. No credentials loaded. To use your gcloud credentials, run 'gcloud auth application-default login'. Original error: oauth2: \"invalid_grant\" \"reauth related error (invalid_rapt)\""
+        ));
+        assert!(!is_auth_error(
+            "Error: Unsupported argument: An argument named \"foo\" is not expected here."
+        ));
     }
 }
@@ -0,0 +1,23 @@
+use crate::{
+    args::ExecArgs,
+    auth,
+    cmd::Cmd,
+    config::{AccountAuth, Config},
+};
+
+pub fn exec(args: ExecArgs, config: &Config) {
+    assert!(
+        config.account_auth(&args.account) != AccountAuth::Skip,
+        "account {} is configured to be skipped",
+        args.account
+    );
+    let account_auth = config.account_auth(&args.account);
+    let session = auth::login(&account_auth, config, true);
+    let (program, program_args) = args.command.split_first().unwrap();
+    // The command can be interactive, e.g. `terraform console`.
+    let status = Cmd::new(program, program_args)
+        .with_env_vars(session.env_vars)
+        .run_interactive();
+    // Exit with the same code of the command, so that infratk can be used in scripts.
+    std::process::exit(status.code().unwrap_or(1));
+}
@@ -4,7 +4,7 @@ use camino::Utf8PathBuf;
 use petgraph::dot::{self, Dot};
 use semver::Version;
 
-use crate::{args::GraphArgs, clipboard, dir, graph::ModulesGraph, provider};
+use crate::{args::GraphArgs, clipboard, dir, graph::ModulesGraph, graph_html, provider};
 
 pub async fn print_graph(args: GraphArgs) {
     assert!(dir::current_dir_is_simpleinfra());
@@ -15,6 +15,15 @@ pub async fn print_graph(args: GraphArgs) {
         None
     };
 
+    if let Some(html_file) = &args.html {
+        // Outdated modules are colored by the viewer, so nodes don't need emojis.
+        let graph = ModulesGraph::new(None);
+        let html = graph_html::to_html(&graph, outdated_packages.as_ref());
+        fs_err::write(html_file, html).unwrap();
+        println!("Graph written to {html_file}");
+        return;
+    }
+
     let graph = ModulesGraph::new(outdated_packages.as_ref());
 
     // Get `graphviz` format
@@ -1,5 +1,180 @@
-use crate::{aws, config::Config};
+use std::{collections::BTreeMap, io::Write as _, process::Command};
 
-pub fn login_to_legacy_aws_account(config: &Config) {
-    aws::legacy_login(config.op_legacy_item_id.as_deref());
+use camino::{Utf8Path, Utf8PathBuf};
+use camino_tempfile::Utf8TempDir;
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::{
+    args::{LegacyLoginArgs, Shell},
+    aws,
+    cmd::AWS_CREDENTIALS_ENV_VARS,
+    config::Config,
+};
+
+/// Shown in the prompt of the subshell.
+const PROMPT_MARKER: &str = "(aws:legacy) ";
+
+pub fn login_to_legacy_aws_account(args: LegacyLoginArgs, config: &Config) {
+    let shell = args.shell.unwrap_or_else(default_shell);
+    let env_vars = aws::legacy_login(config, true).env_vars;
+    if args.subshell {
+        start_subshell(shell, &env_vars);
+    } else {
+        // Only the exports are printed to stdout, so that they can be evaluated.
+        print!("{}", format_exports(shell, &env_vars));
+        eprintln!("✅ Logged in to the legacy account. Evaluate the output to use the credentials.");
+    }
+}
+
+/// Shell of the `SHELL` environment variable, or bash if it's unknown.
+fn default_shell() -> Shell {
+    let shell = std::env::var("SHELL").unwrap_or_default();
+    let shell_name = shell.rsplit('/').next().unwrap_or_default();
+    match shell_name {
+        "zsh" => Shell::Zsh,
+        "fish" => Shell::Fish,
+        "pwsh" | "powershell" => Shell::Powershell,
+        _ if cfg!(windows) && shell.is_empty() => Shell::Powershell,
+        _ => Shell::Bash,
+    }
+}
+
+fn format_exports(shell: Shell, env_vars: &BTreeMap<String, SecretString>) -> String {
+    env_vars
+        .iter()
+        .map(|(key, value)| {
+            let value = value.expose_secret();
+            match shell {
+                Shell::Bash | Shell::Zsh => {
+                    format!("export {key}='{}'\n", value.replace('\'', r"'\''"))
+                }
+                Shell::Fish => format!(
+                    "set -gx {key} '{}'\n",
+                    value.replace('\\', r"\\").replace('\'', r"\'")
+                ),
+                Shell::Powershell => format!("$env:{key} = '{}'\n", value.replace('\'', "''")),
+            }
+        })
+        .collect()
+}
+
+/// Start an interactive shell with the credentials and wait for the user to exit it.
+fn start_subshell(shell: Shell, env_vars: &BTreeMap<String, SecretString>) {
+    // Startup files of the shell, removed when the shell exits.
+    let rc_dir = private_temp_dir();
+    let mut command = match shell {
+        Shell::Bash => {
+            let rc_file = write_rc_file(
+                rc_dir.path(),
+                "bashrc",
+                &format!("[ -f ~/.bashrc ] && . ~/.bashrc\nPS1=\"{PROMPT_MARKER}$PS1\"\n"),
+            );
+            let mut command = Command::new("bash");
+            command.args(["--rcfile", rc_file.as_str()]);
+            command
+        }
+        Shell::Zsh => {
+            // zsh reads `.zshrc` from `ZDOTDIR`.
+            let rc_file = write_rc_file(
+                rc_dir.path(),
+                ".zshrc",
+                &format!(
+                    "ZDOTDIR=~\n[ -f ~/.zshrc ] && . ~/.zshrc\nPROMPT=\"{PROMPT_MARKER}$PROMPT\"\n"
+                ),
+            );
+            let mut command = Command::new("zsh");
+            command.env("ZDOTDIR", rc_file.parent().unwrap());
+            command
+        }
+        Shell::Fish => {
+            let mut command = Command::new("fish");
+            command.args([
+                "--init-command",
+                &format!(
+                    "functions --copy fish_prompt _infratk_fish_prompt; \
+                     function fish_prompt; echo -n '{PROMPT_MARKER}'; _infratk_fish_prompt; end"
+                ),
+            ]);
+            command
+        }
+        Shell::Powershell => {
+            let mut command = Command::new("pwsh");
+            command.args([
+                "-NoExit",
+                "-Command",
+                &format!(
+                    "$infratkPrompt = $function:prompt; \
+                     function global:prompt {{ '{PROMPT_MARKER}' + (& $infratkPrompt) }}"
+                ),
+            ]);
+            command
+        }
+    };
+    for key in AWS_CREDENTIALS_ENV_VARS {
+        command.env_remove(key);
+    }
+    for (key, value) in env_vars {
+        command.env(key, value.expose_secret());
+    }
+    // Scripts can check this variable to know which account the shell uses.
+    command.env("INFRATK_ACCOUNT", "legacy");
+    eprintln!("🐚 Starting a shell with the credentials of the legacy account. Exit it to logout.");
+    let status = command.status().expect("failed to start the shell");
+    if !status.success() {
+        eprintln!("⚠️ The shell exited with {status}");
+    }
+}
+
+/// Temporary directory that only the current user can access, so that other users
+/// can't change the startup files of a shell with the credentials.
+fn private_temp_dir() -> Utf8TempDir {
+    let dir = camino_tempfile::Builder::new()
+        .prefix("infratk-legacy-shell-")
+        .tempdir()
+        .unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        fs_err::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o700)).unwrap();
+    }
+    dir
+}
+
+/// Write a startup file of the subshell in `dir`.
+/// Fails if the file already exists.
+fn write_rc_file(dir: &Utf8Path, name: &str, content: &str) -> Utf8PathBuf {
+    let rc_file = dir.join(name);
+    fs_err::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&rc_file)
+        .unwrap()
+        .write_all(content.as_bytes())
+        .unwrap();
+    rc_file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_are_quoted_for_every_shell() {
+        let env_vars = BTreeMap::from([
+            ("AWS_ACCESS_KEY_ID".to_string(), SecretString::new("AKIA".to_string())),
+            ("AWS_SECRET_ACCESS_KEY".to_string(), SecretString::new("a'b\\c".to_string())),
+        ]);
+        assert_eq!(
+            format_exports(Shell::Bash, &env_vars),
+            "export AWS_ACCESS_KEY_ID='AKIA'\nexport AWS_SECRET_ACCESS_KEY='a'\\''b\\c'\n"
+        );
+        assert_eq!(
+            format_exports(Shell::Fish, &env_vars),
+            "set -gx AWS_ACCESS_KEY_ID 'AKIA'\nset -gx AWS_SECRET_ACCESS_KEY 'a\\'b\\\\c'\n"
+        );
+        assert_eq!(
+            format_exports(Shell::Powershell, &env_vars),
+            "$env:AWS_ACCESS_KEY_ID = 'AKIA'\n$env:AWS_SECRET_ACCESS_KEY = 'a''b\\c'\n"
+        );
+    }
 }
@@ -0,0 +1,195 @@
+use std::{collections::HashSet, fmt};
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::{
+    args::{LintArgs, LintCommand},
+    dir,
+    graph::{Declaration, Link, ModulesGraph},
+    provider, LOCKFILE,
+};
+
+pub fn lint(args: LintArgs) {
+    assert!(dir::current_dir_is_simpleinfra());
+    let errors = match args.command {
+        LintCommand::Graph => lint_graph(&ModulesGraph::new(None)),
+        LintCommand::DeadModules => {
+            let lockfiles = provider::get_all_lockfiles()
+                .iter()
+                .map(|l| dir::strip_current_dir(l))
+                .collect();
+            lint_dead_modules(&ModulesGraph::new(None), lockfiles)
+        }
+    };
+    if errors.is_empty() {
+        println!("✅ No issues found");
+        return;
+    }
+    for error in &errors {
+        println!("❌ {error}");
+    }
+    println!("\nFound {} issues", errors.len());
+    std::process::exit(1);
+}
+
+#[derive(Debug)]
+enum LintError {
+    /// The `source` or `config_path` points to a path that doesn't exist.
+    MissingTarget(Link),
+    /// The `source` or `config_path` points to a path outside of the repository.
+    OutsideRepository(Link),
+    /// Modules that depend on each other.
+    Cycle(Vec<Link>),
+    /// Terraform module that isn't used by any stack, directly or transitively.
+    UnusedModule(Utf8PathBuf),
+    /// Directory with a lockfile but without any terraform or terragrunt file.
+    EmptyStack(Utf8PathBuf),
+    /// Lockfile in a directory that isn't part of the graph of modules:
+    /// the directory has no terraform or terragrunt files and no module depends on it.
+    UnknownLockfile(Utf8PathBuf),
+}
+
+impl fmt::Display for LintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintError::MissingTarget(link) => write!(
+                f,
+                "{}: {} depends on {}, which doesn't exist",
+                link.declaration, link.module, link.dependency
+            ),
+            LintError::OutsideRepository(link) => write!(
+                f,
+                "{}: {} depends on {}, which is outside of the repository",
+                link.declaration, link.module, link.dependency
+            ),
+            LintError::Cycle(cycle) => {
+                let start = &cycle[0].module;
+                write!(f, "dependency cycle starting from {start}:")?;
+                for Link {
+                    dependency,
+                    declaration: Declaration { file, line },
+                    ..
+                } in cycle
+                {
+                    write!(f, "\n  └─ {dependency} ({file}:{line})")?;
+                }
+                Ok(())
+            }
+            LintError::UnusedModule(module) => {
+                write!(f, "{module} isn't used by any stack")
+            }
+            LintError::EmptyStack(stack) => write!(
+                f,
+                "{stack} contains a lockfile but no `.tf` or `.hcl` files"
+            ),
+            LintError::UnknownLockfile(lockfile) => write!(
+                f,
+                "{lockfile} is in a directory that isn't part of the graph of modules"
+            ),
+        }
+    }
+}
+
+fn lint_graph(graph: &ModulesGraph) -> Vec<LintError> {
+    let mut errors = vec![];
+    for link in graph.links() {
+        if !link.dependency.exists() {
+            errors.push(LintError::MissingTarget(link));
+        } else if link.dependency.is_absolute() {
+            // Paths inside the repository are relative to the current directory.
+            errors.push(LintError::OutsideRepository(link));
+        }
+    }
+    errors.extend(graph.cycles().into_iter().map(LintError::Cycle));
+    errors
+}
+
+fn lint_dead_modules(graph: &ModulesGraph, mut lockfiles: Vec<Utf8PathBuf>) -> Vec<LintError> {
+    let stacks: Vec<&Utf8PathBuf> = graph
+        .graph
+        .node_weights()
+        .filter(|m| m.join(LOCKFILE).exists())
+        .collect();
+    let used_modules: HashSet<Utf8PathBuf> = stacks
+        .iter()
+        .flat_map(|s| graph.get_dependencies_of_dir(s))
+        .collect();
+
+    let mut errors = vec![];
+    let mut unused_modules: Vec<&Utf8PathBuf> = graph
+        .graph
+        .node_weights()
+        .filter(|m| m.starts_with("terraform"))
+        .filter(|m| !stacks.contains(m) && !used_modules.contains(*m))
+        .collect();
+    unused_modules.sort();
+    errors.extend(
+        unused_modules
+            .into_iter()
+            .map(|m| LintError::UnusedModule(m.clone())),
+    );
+
+    // The lockfile itself makes its directory a node of the graph,
+    // so only directories with other files or with dependent modules are known.
+    let dependencies: HashSet<Utf8PathBuf> =
+        graph.links().into_iter().map(|l| l.dependency).collect();
+    lockfiles.sort();
+    for lockfile in lockfiles {
+        let stack = lockfile.parent().unwrap();
+        let has_terraform_files = contains_terraform_files(stack);
+        if !has_terraform_files && !dependencies.contains(stack) {
+            errors.push(LintError::UnknownLockfile(lockfile));
+        } else if !has_terraform_files {
+            errors.push(LintError::EmptyStack(stack.to_path_buf()));
+        }
+    }
+    errors
+}
+
+/// Whether the directory contains `.tf` or `.hcl` files, excluding the lockfile.
+fn contains_terraform_files(dir: &Utf8Path) -> bool {
+    dir.read_dir_utf8().unwrap().any(|entry| {
+        let entry = entry.unwrap();
+        let path = entry.path();
+        path.is_file()
+            && entry.file_name() != LOCKFILE
+            && matches!(path.extension(), Some("tf" | "hcl"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::Graph;
+
+    use super::*;
+
+    #[test]
+    fn stray_lockfiles_are_reported() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let stack = dir.path().join("stack");
+        let empty_stack = dir.path().join("empty-stack");
+        let stray = dir.path().join("stray");
+        for d in [&stack, &empty_stack, &stray] {
+            fs_err::create_dir(d).unwrap();
+            fs_err::write(d.join(LOCKFILE), "").unwrap();
+        }
+        fs_err::write(stack.join("main.tf"), "").unwrap();
+        // Every lockfile directory is a node, because lockfiles are `.hcl` files.
+        let mut graph = Graph::new();
+        let stack_index = graph.add_node(stack.clone());
+        let empty_stack_index = graph.add_node(empty_stack.clone());
+        graph.add_node(stray.clone());
+        let declaration = Declaration {
+            file: stack.join("main.tf"),
+            line: 1,
+        };
+        graph.add_edge(stack_index, empty_stack_index, declaration);
+        let graph = ModulesGraph::from_graph(graph);
+
+        let lockfiles = vec![stack.join(LOCKFILE), empty_stack.join(LOCKFILE), stray.join(LOCKFILE)];
+        let errors = lint_dead_modules(&graph, lockfiles);
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(matches!(&errors[0], LintError::EmptyStack(s) if *s == empty_stack));
+        assert!(matches!(&errors[1], LintError::UnknownLockfile(l) if *l == stray.join(LOCKFILE)));
+    }
+}
@@ -1,6 +1,11 @@
 pub mod config_cmd;
+pub mod exec;
 pub mod graph_cmd;
 pub mod legacy_login;
+pub mod lint;
+pub mod order;
+pub mod plan_branch;
 pub mod plan_pr;
 pub mod upgrade;
 pub mod upgrade_provider;
+pub mod why;
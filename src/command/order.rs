@@ -0,0 +1,34 @@
+use camino::Utf8PathBuf;
+
+use crate::{args::OrderArgs, dir, graph::ModulesGraph, LOCKFILE};
+
+pub fn print_order(args: OrderArgs) {
+    assert!(dir::current_dir_is_simpleinfra());
+    let graph = ModulesGraph::new(None);
+    let modules: Vec<Utf8PathBuf> = if args.modules.is_empty() {
+        graph
+            .graph
+            .node_weights()
+            .filter(|m| m.join(LOCKFILE).exists())
+            .cloned()
+            .collect()
+    } else {
+        args.modules
+            .iter()
+            .map(|m| dir::strip_current_dir(m))
+            .collect()
+    };
+    let waves = match graph.apply_waves(&modules) {
+        Ok(waves) => waves,
+        Err(err) => {
+            eprintln!("❌ {err}");
+            std::process::exit(1);
+        }
+    };
+    for (i, wave) in waves.iter().enumerate() {
+        println!("Wave {}:", i + 1);
+        for module in wave {
+            println!("- {module}");
+        }
+    }
+}
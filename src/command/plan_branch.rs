@@ -0,0 +1,30 @@
+use camino::Utf8PathBuf;
+
+use crate::{
+    args::PlanBranch, clipboard, command::plan_pr, config::Config,
+    dir::current_dir_is_simpleinfra, git, pretty_format,
+};
+
+pub fn plan_branch(args: PlanBranch, config: &Config) {
+    assert!(current_dir_is_simpleinfra());
+    let repo = git::repo();
+    let merge_base = repo.git(&["merge-base", &args.base, "HEAD"]).unwrap();
+    let mut files_changed = git::diff_files(&repo, &format!("{merge_base}..HEAD"));
+    if args.uncommitted {
+        let uncommitted_files = repo.changes_except_typechanges().unwrap();
+        for file in uncommitted_files.into_iter().map(Utf8PathBuf::from) {
+            if !files_changed.contains(&file) {
+                files_changed.push(file);
+            }
+        }
+    }
+    println!("Files changed since {}: {:?}", args.base, files_changed);
+    let mut output = plan_pr::plan_changed_files(&files_changed, config);
+    let removed_stacks = plan_pr::get_removed_stacks(&files_changed);
+    output.extend(plan_pr::plan_removed_stacks(&merge_base, removed_stacks, config));
+    let output_str = pretty_format::format_output_with_reasons(output);
+    println!("{output_str}");
+    if args.clipboard {
+        clipboard::copy_to_clipboard(output_str);
+    }
+}
@@ -1,122 +1,276 @@
-use std::collections::BTreeMap;
+use std::fmt;
 
-use camino::{Utf8Path, Utf8PathBuf};
+use camino::Utf8PathBuf;
+use inquire::Select;
 
 use crate::{
     args::PlanPr,
-    aws, clipboard,
-    cmd::Cmd,
-    cmd_runner::{CmdRunner, PlanOutcome},
+    auth, clipboard,
+    cmd_runner::{CmdRunner, PlanOutcome, PlanReason},
     config::Config,
-    dir::current_dir_is_simpleinfra,
-    git::assert_current_branch_is_same_as_pr,
-    grouped_dirs::GroupedDirs,
-    pretty_format, LOCKFILE,
+    dir::{self, current_dir_is_simpleinfra},
+    git::{self, Worktree},
+    github::{GitHubClient, PullRequest},
+    graph::ModulesGraph,
+    grouped_dirs::{is_terraform_dir, GroupedDirs},
+    pr_comment, pretty_format, LOCKFILE,
 };
 
-pub fn plan_pr(args: PlanPr, config: &Config) {
+pub async fn plan_pr(args: PlanPr, config: &Config) {
     assert!(current_dir_is_simpleinfra());
-    assert_current_branch_is_same_as_pr(&args.pr);
-    let files_changed = get_files_changes(args.pr);
+    let github = GitHubClient::new(config).unwrap();
+    let pr = github.get_pr(&args.pr).await.unwrap();
+    let repo = git::repo();
+    let pr_head = git::fetch_pr_head(&repo, &pr).unwrap();
+    // Plan in a separate worktree, so that the local checkout isn't touched.
+    let mut worktree = Worktree::add(repo, "plan-pr", &pr_head);
+    println!("ℹ️ Planning PR #{} ({}) in {}", pr.number, pr.head_ref, worktree.path());
+    worktree.enter();
+
+    let files_changed: Vec<Utf8PathBuf> = pr.files.iter().map(|f| f.path.clone()).collect();
     println!("Files changed in PR: {:?}", files_changed);
-    let lock_files = get_lock_files(files_changed);
-    println!("Lock files changed in PR: {:?}", lock_files);
-    let directories: Vec<&Utf8Path> = lock_files
+    let mut graph = ModulesGraph::new(None);
+    let mut affected_stacks = get_affected_stacks(&files_changed, &graph);
+    if check_base_is_up_to_date(&worktree, &pr, &pr_head, &graph, &affected_stacks.stacks) {
+        // The worktree changed, so the graph might be different.
+        graph = ModulesGraph::new(None);
+        affected_stacks = get_affected_stacks(&files_changed, &graph);
+    }
+    let mut output = plan_affected_stacks(affected_stacks, config);
+    // Renamed stacks are planned for destruction at their old location.
+    let removed_files: Vec<Utf8PathBuf> = pr
+        .files
         .iter()
-        .map(|file| file.parent().unwrap())
+        .filter_map(|f| match f.status.as_str() {
+            "removed" => Some(f.path.clone()),
+            "renamed" => f.previous_filename.clone(),
+            _ => None,
+        })
         .collect();
-    let output = plan_directories(directories, config);
-    let output_str = pretty_format::format_output(output);
+    let removed_stacks = get_removed_stacks(&removed_files);
+    // Remove the worktree before creating the one of the base.
+    drop(worktree);
+    output.extend(plan_removed_stacks(&pr.base_sha, removed_stacks, config));
+    let output_str = pretty_format::format_output_with_reasons(output);
     println!("{output_str}");
+    if args.comment {
+        pr_comment::upsert_plan_comment(&github, pr.number, &output_str)
+            .await
+            .unwrap();
+    }
     if args.clipboard {
         clipboard::copy_to_clipboard(output_str);
     }
 }
 
-fn plan_directories(
-    directories: Vec<&Utf8Path>,
+/// Stacks affected by some changed files.
+pub struct AffectedStacks {
+    /// Sorted by dependencies.
+    pub stacks: Vec<Utf8PathBuf>,
+    /// Directories containing the changed files.
+    changed_dirs: Vec<Utf8PathBuf>,
+}
+
+/// Plan the stacks that changed and the stacks that depend on the modules that changed.
+pub fn plan_changed_files(
+    files_changed: &[Utf8PathBuf],
     config: &Config,
-) -> Vec<(Utf8PathBuf, PlanOutcome)> {
-    let grouped_dirs = GroupedDirs::new(directories);
+) -> Vec<(Utf8PathBuf, PlanOutcome, PlanReason)> {
+    let graph = ModulesGraph::new(None);
+    let affected_stacks = get_affected_stacks(files_changed, &graph);
+    plan_affected_stacks(affected_stacks, config)
+}
 
-    let mut output: Vec<(Utf8PathBuf, PlanOutcome)> = vec![];
-    if grouped_dirs.contains_legacy_account() {
-        let legacy_tg_dirs = grouped_dirs.legacy_terragrunt_dirs();
-        let o = plan_legacy_dirs(grouped_dirs.terraform_dirs(), legacy_tg_dirs, config);
-        output.extend(o);
+/// Get the stacks that changed and the stacks that depend on the modules that changed.
+pub fn get_affected_stacks(files_changed: &[Utf8PathBuf], graph: &ModulesGraph) -> AffectedStacks {
+    let lock_files = get_lock_files(files_changed.to_vec());
+    let changed_dirs = get_changed_dirs(files_changed);
+    let changed_dirs_in_graph: Vec<&Utf8PathBuf> = changed_dirs
+        .iter()
+        .filter(|d| graph.contains(d))
+        .collect();
+    let mut stacks = graph.get_dependent_modules_containing_lockfile(&changed_dirs_in_graph);
+    // Stacks with a changed lockfile are planned even if they aren't part of the graph.
+    for lock_file in &lock_files {
+        let stack = lock_file.parent().unwrap().to_path_buf();
+        // Removed stacks are planned separately.
+        if !stacks.contains(&stack) && stack.exists() {
+            stacks.push(stack);
+        }
+    }
+    let stacks = graph.sort_by_dependencies(&stacks);
+    AffectedStacks {
+        stacks,
+        changed_dirs,
     }
+}
 
-    let sso_terragrunt_dirs = grouped_dirs.sso_terragrunt_dirs();
-    let o = plan_terragrunt_with_sso(&sso_terragrunt_dirs);
-    output.extend(o);
+pub fn plan_affected_stacks(
+    affected_stacks: AffectedStacks,
+    config: &Config,
+) -> Vec<(Utf8PathBuf, PlanOutcome, PlanReason)> {
+    let AffectedStacks {
+        stacks,
+        changed_dirs,
+    } = affected_stacks;
+    println!("ℹ️ Stacks to plan: {stacks:?}");
 
-    output
+    plan_directories(stacks, config, false)
+        .into_iter()
+        .map(|(dir, outcome)| {
+            let reason = if changed_dirs.contains(&dir) {
+                PlanReason::Changed
+            } else {
+                PlanReason::Dependent
+            };
+            (dir, outcome, reason)
+        })
+        .collect()
 }
 
-fn plan_legacy_dirs<T, U>(
-    terraform_dirs: Vec<T>,
-    terragrunt_dirs: Vec<U>,
-    config: &Config,
-) -> Vec<(Utf8PathBuf, PlanOutcome)>
-where
-    T: AsRef<Utf8Path>,
-    U: AsRef<Utf8Path>,
-{
-    let terraform_dirs = terraform_dirs
-        .iter()
-        .map(|d| d.as_ref())
-        .collect::<Vec<_>>();
-    let terragrunt_dirs = terragrunt_dirs
+/// Stacks whose lockfile was removed together with their directory.
+pub fn get_removed_stacks(removed_files: &[Utf8PathBuf]) -> Vec<Utf8PathBuf> {
+    get_lock_files(removed_files.to_vec())
         .iter()
-        .map(|d| d.as_ref())
-        .collect::<Vec<_>>();
-    // logout before login, to avoid issues with multiple profiles
-    aws::sso_logout();
-    let login_env_vars = aws::legacy_login(config.op_legacy_item_id.as_deref());
-    let cmd_runner = CmdRunner::new(login_env_vars);
+        .map(|lock_file| lock_file.parent().unwrap().to_path_buf())
+        .filter(|stack| !stack.exists())
+        .collect()
+}
 
-    let mut output = vec![];
-    for d in terraform_dirs {
-        let o = cmd_runner.terraform_plan(d);
-        output.push((d.to_path_buf(), o));
+/// Plan the destruction of the removed stacks, checking out `base_commit` in a worktree,
+/// where the stacks still exist.
+pub fn plan_removed_stacks(
+    base_commit: &str,
+    removed_stacks: Vec<Utf8PathBuf>,
+    config: &Config,
+) -> Vec<(Utf8PathBuf, PlanOutcome, PlanReason)> {
+    if removed_stacks.is_empty() {
+        return vec![];
     }
-    for d in terragrunt_dirs {
-        let o = cmd_runner.terragrunt_plan(d);
-        output.push((d.to_path_buf(), o));
+    println!("ℹ️ Stacks being removed: {removed_stacks:?}");
+    let mut worktree = Worktree::add(git::repo(), "plan-base", base_commit);
+    worktree.enter();
+    // Stacks that depend on others must be destroyed first.
+    let mut removed_stacks = ModulesGraph::new(None).sort_by_dependencies(&removed_stacks);
+    removed_stacks.reverse();
+    plan_directories(removed_stacks, config, true)
+        .into_iter()
+        .map(|(dir, outcome)| (dir, outcome, PlanReason::Removed))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StaleBaseAction {
+    Continue,
+    Merge,
+    Rebase,
+}
+
+impl fmt::Display for StaleBaseAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Continue => write!(f, "Plan the PR as it is"),
+            Self::Merge => write!(f, "Merge the base branch into the PR before planning"),
+            Self::Rebase => write!(f, "Rebase the PR on the base branch before planning"),
+        }
     }
-    output
 }
 
-fn plan_terragrunt_with_sso<T>(
-    terragrunt_sso_dirs: &BTreeMap<&str, Vec<T>>,
-) -> Vec<(Utf8PathBuf, PlanOutcome)>
-where
-    T: AsRef<Utf8Path>,
-{
-    let terragrunt_sso_dirs = terragrunt_sso_dirs
-        .iter()
-        .map(|(k, v)| (*k, v.iter().map(|d| d.as_ref()).collect::<Vec<_>>()))
-        .collect::<BTreeMap<_, _>>();
-    let mut outcome = vec![];
-    for (account, dirs) in terragrunt_sso_dirs {
-        aws::sso_logout();
-        aws::sso_login(account);
-        for d in dirs {
-            let plan_outcome = CmdRunner::new(BTreeMap::new()).terragrunt_plan(d);
-            outcome.push((d.to_path_buf(), plan_outcome));
+/// Warn if the base branch has commits that aren't in the PR and that touch the stacks to plan,
+/// because the plan might be misleading.
+/// Offer to merge or rebase the base branch in `worktree`.
+/// Returns `true` if the worktree was updated.
+fn check_base_is_up_to_date(
+    worktree: &Worktree,
+    pr: &PullRequest,
+    pr_head: &str,
+    graph: &ModulesGraph,
+    stacks: &[Utf8PathBuf],
+) -> bool {
+    let repo = worktree.repo();
+    let base_head = git::fetch_branch(repo, &pr.clone_url, &pr.base_ref);
+    let merge_base = repo.git(&["merge-base", &base_head, pr_head]).unwrap();
+    if merge_base == base_head {
+        return false;
+    }
+    let base_files = git::diff_files(repo, &format!("{merge_base}..{base_head}"));
+    let base_stacks = get_affected_stacks(&base_files, graph).stacks;
+    let stale_stacks: Vec<&Utf8PathBuf> = stacks.iter().filter(|s| base_stacks.contains(s)).collect();
+    if stale_stacks.is_empty() {
+        return false;
+    }
+    println!(
+        "⚠️ The PR is behind `{}`, which changed these stacks since the PR branched off: {stale_stacks:?}. The plan might be misleading.",
+        pr.base_ref
+    );
+    let options = vec![
+        StaleBaseAction::Continue,
+        StaleBaseAction::Merge,
+        StaleBaseAction::Rebase,
+    ];
+    let action = Select::new("What do you want to do?", options)
+        .prompt()
+        .unwrap_or(StaleBaseAction::Continue);
+    let result = match action {
+        StaleBaseAction::Continue => return false,
+        StaleBaseAction::Merge => worktree.git(&["merge", "--no-edit", &base_head]),
+        StaleBaseAction::Rebase => worktree.git(&["rebase", &base_head]),
+    };
+    if let Err(err) = result {
+        println!("⚠️ Can't update the PR with `{}`, planning the PR as it is: {err:?}", pr.base_ref);
+        let abort_cmd = match action {
+            StaleBaseAction::Rebase => "rebase",
+            _ => "merge",
+        };
+        worktree.git(&[abort_cmd, "--abort"]).unwrap();
+        return false;
+    }
+    true
+}
+
+/// Directories containing the changed files, relative to the current directory.
+fn get_changed_dirs(files: &[Utf8PathBuf]) -> Vec<Utf8PathBuf> {
+    let mut dirs = vec![];
+    for file in files {
+        let dir = dir::strip_current_dir(file.parent().unwrap());
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
         }
     }
-    outcome
+    dirs
 }
 
-fn get_files_changes(pr: String) -> Vec<Utf8PathBuf> {
-    Cmd::new("gh", ["pr", "diff", &pr, "--name-only"])
-        .hide_stdout()
-        .run()
-        .stdout()
-        .lines()
-        .map(Utf8PathBuf::from)
-        .collect()
+/// If `destroy` is true, plan the destruction of the directories.
+fn plan_directories(
+    directories: Vec<Utf8PathBuf>,
+    config: &Config,
+    destroy: bool,
+) -> Vec<(Utf8PathBuf, PlanOutcome)> {
+    let grouped_dirs = GroupedDirs::new(directories, config);
+
+    let mut output = vec![];
+    for group in grouped_dirs.groups() {
+        println!("ℹ️ Planning accounts {:?}", group.accounts);
+        let cmd_runner =
+            CmdRunner::new(|reuse_cache| auth::login(&group.auth, config, reuse_cache));
+        for d in &group.dirs {
+            let o = if is_terraform_dir(d) {
+                // The worktree is new, so terraform needs to be initialized.
+                cmd_runner.terraform_init(d);
+                if destroy {
+                    cmd_runner.terraform_plan_destroy(d)
+                } else {
+                    cmd_runner.terraform_plan(d)
+                }
+            } else if destroy {
+                cmd_runner.terragrunt_plan_destroy(d)
+            } else {
+                cmd_runner.terragrunt_plan(d)
+            };
+            output.push((d.clone(), o));
+        }
+    }
+    output
 }
 
 fn get_lock_files(files: Vec<Utf8PathBuf>) -> Vec<Utf8PathBuf> {
@@ -148,4 +302,65 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn changed_dirs_are_deduplicated() {
+        let files = vec![
+            Utf8PathBuf::from("module1/main.tf"),
+            Utf8PathBuf::from("module1/.terraform.lock.hcl"),
+            Utf8PathBuf::from("module2/variables.tf"),
+        ];
+        let dirs = get_changed_dirs(&files);
+        assert_eq!(
+            dirs,
+            vec![Utf8PathBuf::from("module1"), Utf8PathBuf::from("module2")]
+        );
+    }
+
+    #[test]
+    fn only_deleted_stacks_are_removed() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let existing_stack = dir.path().join("existing-stack");
+        fs_err::create_dir(&existing_stack).unwrap();
+        let deleted_stack = dir.path().join("deleted-stack");
+        let files = vec![
+            // The directory still exists, so only the lockfile was removed.
+            existing_stack.join(LOCKFILE),
+            deleted_stack.join(LOCKFILE),
+            deleted_stack.join("main.tf"),
+        ];
+        let removed_stacks = get_removed_stacks(&files);
+        assert_eq!(removed_stacks, vec![deleted_stack]);
+    }
+
+    #[test]
+    fn base_check_runs_in_detached_worktree() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let mut all_args = vec!["-c", "user.name=infratk", "-c", "user.email=i@example.com"];
+            all_args.extend(args);
+            git_cmd::git_in_dir(dir.path(), &all_args).unwrap()
+        };
+        git(&["init", "--initial-branch", "main"]);
+        git(&["commit", "--allow-empty", "-m", "initial"]);
+        let pr_head = git(&["rev-parse", "HEAD"]);
+        let worktree = Worktree::add(git_cmd::Repo::new(dir.path()).unwrap(), "test", &pr_head);
+        fs_err::write(dir.path().join("README.md"), "").unwrap();
+        git(&["add", "README.md"]);
+        git(&["commit", "-m", "base"]);
+
+        let pr = PullRequest {
+            number: 1,
+            head_ref: "feature".to_string(),
+            head_sha: pr_head.clone(),
+            base_ref: "main".to_string(),
+            base_sha: pr_head.clone(),
+            clone_url: dir.path().to_string(),
+            files: vec![],
+        };
+        let graph = ModulesGraph::from_graph(petgraph::Graph::new());
+        let stacks = vec![Utf8PathBuf::from("stack")];
+        // The base changed, but not the stacks to plan, so the worktree isn't updated.
+        assert!(!check_base_is_up_to_date(&worktree, &pr, &pr_head, &graph, &stacks));
+    }
 }
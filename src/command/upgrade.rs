@@ -1,26 +1,31 @@
 use camino::{Utf8Path, Utf8PathBuf};
+use git_cmd::Repo;
 use tracing::debug;
 
 use crate::{
-    args::UpgradeArgs, aws, clipboard, cmd_runner::{CmdRunner, PlanOutcome}, config::Config, dir, envirnoment::assert_aws_env_is_not_set, git, graph::ModulesGraph, grouped_dirs::GroupedDirs, pretty_format, select
+    args::UpgradeArgs, auth, clipboard, command::why, cmd_runner::{CmdRunner, PlanOutcome}, config::{AccountAuth, Config}, dir, git, graph::ModulesGraph, grouped_dirs::GroupedDirs, pretty_format, select
 };
 
 pub fn upgrade(args: UpgradeArgs, config: &Config) {
     let repo = git::repo();
-    assert_aws_env_is_not_set();
 
     let plan_outcome = if args.git {
-        let changed_files = repo
-            .changes_except_typechanges()
-            .unwrap()
+        let graph = ModulesGraph::new(None);
+        let mut changed_files = get_changed_files(&repo, &args)
             .iter()
-            .map(Utf8PathBuf::from)
-            .map(|p| dir::get_stripped_parent(&p))
+            .map(dir::get_stripped_parent)
+            // Ignore files that aren't part of a module, e.g. the README.
+            .filter(|d| graph.contains(d))
             .collect::<Vec<_>>();
-        let graph = ModulesGraph::new(None);
+        changed_files.sort();
+        changed_files.dedup();
         let dependent_modules = graph.get_dependent_modules_containing_lockfile(&changed_files);
+        let dependent_modules = graph.sort_by_dependencies(&dependent_modules);
         println!("ℹ️ Upgrading dependent modules of {changed_files:?}: {dependent_modules:?}");
-        let grouped_dirs = GroupedDirs::new(dependent_modules);
+        if args.verbose {
+            print_dependency_chains(&graph, &changed_files, &dependent_modules);
+        }
+        let grouped_dirs = GroupedDirs::new(dependent_modules, config);
         grouped_dirs.upgrade_all(config)
     } else {
         let git_root = git::git_root(&repo);
@@ -28,7 +33,8 @@ pub fn upgrade(args: UpgradeArgs, config: &Config) {
         let accounts = list_directories_at_path(&tg_accounts);
         let selected_accounts = select::select_accounts(accounts);
         println!("Selected accounts: {:?}", selected_accounts);
-        upgrade_accounts(selected_accounts, config)
+        let graph = ModulesGraph::new(None);
+        upgrade_accounts(selected_accounts, &graph, config)
     };
     let output_str = pretty_format::format_output(plan_outcome);
     println!("{output_str}");
@@ -37,19 +43,64 @@ pub fn upgrade(args: UpgradeArgs, config: &Config) {
     }
 }
 
+/// Files changed according to the git options of `args`.
+fn get_changed_files(repo: &Repo, args: &UpgradeArgs) -> Vec<Utf8PathBuf> {
+    if let Some(since) = &args.since {
+        git::diff_files(repo, &format!("{since}...HEAD"))
+    } else if args.staged {
+        git::staged_files(repo)
+    } else if let Some(commit) = &args.commit {
+        git::commit_files(repo, commit)
+    } else {
+        repo.changes_except_typechanges()
+            .unwrap()
+            .iter()
+            .map(Utf8PathBuf::from)
+            .collect()
+    }
+}
+
+/// For every dependent module, print the shortest chain of dependencies
+/// that leads to one of the changed directories.
+fn print_dependency_chains(
+    graph: &ModulesGraph,
+    changed_dirs: &[Utf8PathBuf],
+    dependent_modules: &[Utf8PathBuf],
+) {
+    for module in dependent_modules {
+        let shortest_chain = changed_dirs
+            .iter()
+            .filter_map(|changed_dir| graph.dependency_chain(module, changed_dir))
+            .min_by_key(|chain| chain.len());
+        match shortest_chain {
+            Some(chain) if !chain.is_empty() => {
+                println!("ℹ️ {module} is upgraded because:\n{}", why::format_chain(&chain));
+            }
+            // The module is one of the changed directories.
+            _ => println!("ℹ️ {module} is upgraded because it changed"),
+        }
+    }
+}
+
 fn upgrade_accounts(
     accounts: Vec<Utf8PathBuf>,
+    graph: &ModulesGraph,
     config: &Config,
 ) -> Vec<(Utf8PathBuf, PlanOutcome)> {
     let mut outcome = vec![];
     for account in accounts {
-        // logout before login, to avoid issues with multiple profiles
-        aws::sso_logout();
-        let env_vars = aws::login(account.file_name().unwrap(), config);
-        let cmd_runner = CmdRunner::new(env_vars);
+        let account_dir = account.file_name().unwrap();
+        if config.account_auth(account_dir) == AccountAuth::Skip {
+            println!("⏭️ Skipping account {account_dir}");
+            continue;
+        }
+        let account_auth = config.account_auth(account_dir);
+        let cmd_runner =
+            CmdRunner::new(|reuse_cache| auth::login(&account_auth, config, reuse_cache));
         let states = list_directories_at_path(&account);
         let selected_states = select::select_states(states);
         println!("Selected states: {:?}", selected_states);
+        let selected_states = graph.sort_by_dependencies(&selected_states);
         for state in selected_states {
             // Update lockfile
             cmd_runner.terragrunt_init_upgrade(&state);
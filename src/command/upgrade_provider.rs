@@ -6,27 +6,80 @@ use semver::Version;
 use std::fmt;
 
 use crate::{
+    args::UpgradeProviderArgs,
+    cmd_runner::PlanOutcome,
     config::Config,
     dir::{self, current_dir_is_simpleinfra},
-    envirnoment::assert_aws_env_is_not_set,
+    git::{self, Worktree},
+    github::GitHubClient,
+    graph::ModulesGraph,
     grouped_dirs, pretty_format,
     provider::{self, get_all_lockfiles, get_all_providers},
+    LOCKFILE,
 };
 
-pub async fn upgrade_provider(config: &Config) {
+pub async fn upgrade_provider(args: UpgradeProviderArgs, config: &Config) {
     assert!(current_dir_is_simpleinfra());
-    assert_aws_env_is_not_set();
+    let github = (args.open_pr || args.batch).then(|| GitHubClient::new(config).unwrap());
+    // The PRs are opened against the default branch, so upgrade and plan it in a separate
+    // worktree, so that the PRs contain what was planned and the current branch isn't touched.
+    let pr_worktree = match &github {
+        Some(github) => {
+            let (mut worktree, base_head) = default_branch_worktree(github).await.unwrap();
+            worktree.enter();
+            Some((worktree, base_head))
+        }
+        None => None,
+    };
     let lockfiles = get_all_lockfiles();
     let providers = get_all_providers(&lockfiles);
     let outdated_providers = provider::outdated_providers(providers).await.unwrap();
     println!("\nOutdated providers: {outdated_providers}");
+    if args.batch {
+        let github = github.as_ref().unwrap();
+        let (worktree, base_head) = pr_worktree.as_ref().unwrap();
+        let result = upgrade_in_batches(github, worktree, base_head, &outdated_providers, config).await;
+        // `exit` doesn't run the destructors, so remove the worktree before.
+        drop(pr_worktree);
+        if let Err(err) = result {
+            eprintln!("❌ {err:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
     let providers_list = outdated_providers.providers.keys().cloned().collect();
     let selected_providers = select_providers(providers_list);
 
-    update_lockfiles(&outdated_providers, selected_providers, config);
+    let outcome = update_lockfiles(&outdated_providers, &selected_providers, config);
+    let output_str = pretty_format::format_output(outcome.clone());
+    println!("{output_str}");
+    if args.open_pr {
+        let github = github.as_ref().unwrap();
+        let (worktree, _) = pr_worktree.as_ref().unwrap();
+        let pr_url = open_pr(github, worktree, &outdated_providers, &selected_providers, outcome)
+            .await
+            .unwrap();
+        match pr_url {
+            Some(url) => println!("✅ PR opened: {url}"),
+            None => println!("ℹ️ No lockfile can be upgraded without changes: PR not opened"),
+        }
+    }
 }
 
-fn update_lockfiles(providers: &Providers, selected_providers: Vec<String>, config: &Config) {
+fn update_lockfiles(
+    providers: &Providers,
+    selected_providers: &[String],
+    config: &Config,
+) -> Vec<(Utf8PathBuf, PlanOutcome)> {
+    let all_dirs = get_outdated_dirs(providers, selected_providers);
+    let grouped_dirs = grouped_dirs::GroupedDirs::new(all_dirs, config);
+
+    grouped_dirs.upgrade_all(config)
+}
+
+/// Directories of the lockfiles that contain an outdated version of the selected providers,
+/// sorted by dependencies.
+fn get_outdated_dirs(providers: &Providers, selected_providers: &[String]) -> Vec<Utf8PathBuf> {
     // Filter out the providers that were not selected
     let filtered_providers = providers
         .providers
@@ -34,16 +87,143 @@ fn update_lockfiles(providers: &Providers, selected_providers: Vec<String>, conf
         .filter(|(k, _)| selected_providers.contains(k))
         .collect::<BTreeMap<_, _>>();
 
-    let all_dirs: Vec<Utf8PathBuf> = filtered_providers
+    let mut all_dirs: Vec<Utf8PathBuf> = filtered_providers
         .values()
         .flat_map(|v| v.versions.values())
         .flat_map(|paths| get_parents(paths.clone()))
         .collect();
+    // A lockfile can contain multiple selected providers.
+    all_dirs.sort();
+    all_dirs.dedup();
+
+    ModulesGraph::new(None).sort_by_dependencies(&all_dirs)
+}
+
+enum BatchResult {
+    Created(String),
+    Skipped,
+    Failed(anyhow::Error),
+}
+
+/// Fetch the default branch of the repository and check it out in a new worktree.
+/// Returns the worktree and the commit checked out in it.
+async fn default_branch_worktree(github: &GitHubClient) -> anyhow::Result<(Worktree, String)> {
+    let repo = git::repo();
+    let repository = github.get_repository().await?;
+    let base_head = git::fetch_branch(&repo, &repository.clone_url, &repository.default_branch);
+    let worktree = Worktree::add(repo, "upgrade-provider", &base_head);
+    println!("ℹ️ Upgrading `{}` in {}", repository.default_branch, worktree.path());
+    Ok((worktree, base_head))
+}
+
+/// For every outdated provider, upgrade it starting from `base_head` and open a PR.
+/// `worktree` must be the current directory.
+async fn upgrade_in_batches(
+    github: &GitHubClient,
+    worktree: &Worktree,
+    base_head: &str,
+    providers: &Providers,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let mut results = vec![];
+    for provider in providers.providers.keys() {
+        println!("\n⬆️ Upgrading {provider}");
+        let selected_providers = [provider.clone()];
+        let dirs = get_outdated_dirs(providers, &selected_providers);
+        let outcome = grouped_dirs::GroupedDirs::new(dirs, config).upgrade_provider(provider, config);
+        let result = match open_pr(github, worktree, providers, &selected_providers, outcome).await {
+            Ok(Some(url)) => BatchResult::Created(url),
+            Ok(None) => BatchResult::Skipped,
+            Err(err) => BatchResult::Failed(err),
+        };
+        // Discard the upgraded lockfiles before upgrading the next provider.
+        worktree.git(&["checkout", "--force", "--detach", base_head])?;
+        results.push((provider, result));
+    }
+
+    println!("\n| Provider | PR |\n| --- | --- |");
+    for (provider, result) in results {
+        let pr = match result {
+            BatchResult::Created(url) => format!("✅ {url}"),
+            BatchResult::Skipped => "⏭️ skipped: every module has changes".to_string(),
+            BatchResult::Failed(err) => format!("❌ failed: {err}"),
+        };
+        println!("| `{provider}` | {pr} |");
+    }
+    Ok(())
+}
+
+/// Commit the lockfiles of the modules without changes in a new branch, push it and open a PR.
+/// The branch starts from the commit checked out in `worktree`, which must be the current
+/// directory.
+/// Returns the URL of the PR, or `None` if there's nothing to commit.
+async fn open_pr(
+    github: &GitHubClient,
+    worktree: &Worktree,
+    providers: &Providers,
+    selected_providers: &[String],
+    outcome: Vec<(Utf8PathBuf, PlanOutcome)>,
+) -> anyhow::Result<Option<String>> {
+    let lockfiles: Vec<String> = outcome
+        .iter()
+        .filter(|(_, o)| *o == PlanOutcome::NoChanges)
+        .map(|(dir, _)| dir::strip_current_dir(dir).join(LOCKFILE).to_string())
+        .collect();
+    if lockfiles.is_empty() {
+        return Ok(None);
+    }
 
-    let grouped_dirs = grouped_dirs::GroupedDirs::new(all_dirs);
+    let branch = format!(
+        "infratk/upgrade-{}",
+        selected_providers
+            .iter()
+            .map(|p| p.replace('/', "-"))
+            .collect::<Vec<_>>()
+            .join("-")
+    );
+    let title = format!("Upgrade {} provider", selected_providers.join(", "));
+    // The branch of a previous run that failed is overwritten.
+    worktree.git(&["checkout", "-B", &branch])?;
+    let mut add_args = vec!["add", "--"];
+    add_args.extend(lockfiles.iter().map(String::as_str));
+    worktree.git(&add_args)?;
+    worktree.git(&["commit", "-m", &title])?;
+    worktree.git(&["push", "--force", "--set-upstream", "origin", &branch])?;
 
-    let outcome = grouped_dirs.upgrade_all(config);
-    pretty_format::format_output(outcome);
+    let body = pr_body(providers, selected_providers, outcome);
+    let pr_url = github.create_pr(&branch, &title, &body).await?;
+    Ok(Some(pr_url))
+}
+
+fn pr_body(
+    providers: &Providers,
+    selected_providers: &[String],
+    outcome: Vec<(Utf8PathBuf, PlanOutcome)>,
+) -> String {
+    let mut body = String::from("## ⬆️ Upgraded providers\n");
+    for provider in selected_providers {
+        let Some(versions) = providers.providers.get(provider) else {
+            continue;
+        };
+        let old_versions = versions
+            .versions
+            .keys()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let latest_version = versions
+            .latest_version
+            .as_ref()
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| "latest".to_string());
+        body.push_str(&format!("- `{provider}`: {old_versions} → {latest_version}\n"));
+    }
+    body.push_str(
+        "\nOnly the lockfiles of the modules without changes are included in this PR.\n\n",
+    );
+    let output_str = pretty_format::format_output(outcome);
+    body.push_str(&pretty_format::strip_ansi_escapes(&output_str));
+    body
 }
 
 fn get_parents(paths: Vec<Utf8PathBuf>) -> Vec<Utf8PathBuf> {
@@ -87,6 +267,8 @@ impl fmt::Display for Providers {
 pub struct ProviderVersions {
     /// <version> -> <lockfile where the version is present>
     pub versions: BTreeMap<Version, Vec<Utf8PathBuf>>,
+    /// Latest version published in the registry, if known.
+    pub latest_version: Option<Version>,
 }
 
 impl fmt::Display for ProviderVersions {
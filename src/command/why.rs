@@ -0,0 +1,42 @@
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::{
+    args::WhyArgs,
+    dir,
+    graph::{Link, ModulesGraph},
+};
+
+pub fn why(args: WhyArgs) {
+    assert!(dir::current_dir_is_simpleinfra());
+    let from = module_of_path(&args.from);
+    let to = module_of_path(&args.to);
+    let graph = ModulesGraph::new(None);
+    match graph.dependency_chain(&to, &from) {
+        Some(chain) => print!("{}", format_chain(&chain)),
+        None => println!("{to} doesn't depend on {from}"),
+    }
+}
+
+/// Print the chain of dependencies, starting from the module that depends on all the others.
+pub fn format_chain(chain: &[Link]) -> String {
+    let mut output_str = String::new();
+    if let Some(first) = chain.first() {
+        output_str.push_str(&format!("{}\n", first.module));
+    }
+    for link in chain {
+        output_str.push_str(&format!(
+            "  └─ {} (declared in {})\n",
+            link.dependency, link.declaration
+        ));
+    }
+    output_str
+}
+
+/// If `path` is a file, return its directory, relative to the current directory.
+fn module_of_path(path: &Utf8Path) -> Utf8PathBuf {
+    if path.is_file() {
+        dir::get_stripped_parent(&path.to_path_buf())
+    } else {
+        dir::strip_current_dir(path)
+    }
+}
@@ -1,15 +1,169 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Context;
+use camino::Utf8PathBuf;
 use serde::{Deserialize, Serialize};
 
+use crate::secret::SecretSource;
+
 const CONFIG_FILE: &str = "config.toml";
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
-    /// ITEM ID for 1Password aws legacy account
+    /// ITEM ID for 1Password aws legacy account.
+    /// Shortcut for a `legacy_mfa` with the `1password` backend.
     pub op_legacy_item_id: Option<String>,
+    /// Where to read the MFA code of the legacy account.
+    /// If missing, it's asked interactively.
+    pub legacy_mfa: Option<SecretSource>,
+    /// How to get the session credentials of the legacy account.
+    #[serde(default, skip_serializing_if = "LegacyLoginConfig::is_default")]
+    pub legacy_login: LegacyLoginConfig,
+    /// GitHub token, used if `GITHUB_TOKEN` isn't set and the GitHub CLI isn't logged in.
+    pub github_token: Option<String>,
+    /// URL of the GitHub API. Defaults to `https://api.github.com`.
+    pub github_api_url: Option<String>,
+    /// GitHub repository in the `owner/name` format.
+    /// Defaults to the repository of the `upstream` or `origin` git remote.
+    pub github_repo: Option<String>,
+    /// How to authenticate with the accounts under `terragrunt/accounts`,
+    /// by name of the account directory.
+    /// Accounts that aren't listed use [`AccountAuth::default_for`].
+    /// The directories under `terraform` always use [`AccountAuth::Legacy`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub accounts: BTreeMap<String, AccountAuth>,
+}
+
+/// The legacy account is accessed with the session credentials returned by STS `GetSessionToken`,
+/// using the long-term credentials of an IAM user and its MFA device.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LegacyLoginConfig {
+    /// Profile of `~/.aws/credentials` with the long-term credentials. Defaults to `default`.
+    pub profile: Option<String>,
+    /// ARN of the MFA device.
+    /// Defaults to the `mfa_serial` of the profile in `~/.aws/config` or `~/.aws/credentials`.
+    pub mfa_serial: Option<String>,
+    /// Defaults to `https://sts.amazonaws.com`.
+    pub sts_endpoint: Option<String>,
+    /// Region used to sign the STS requests. Defaults to `us-east-1`.
+    pub sts_region: Option<String>,
+    /// How long the session credentials are valid. Defaults to 12 hours.
+    pub duration_seconds: Option<u32>,
+    /// Command that prints the credentials as `export KEY=value` lines, e.g. a script
+    /// that reads static credentials from a password manager.
+    /// If set, it's used instead of STS and of the other options.
+    pub credentials_command: Option<Vec<String>>,
+}
+
+impl LegacyLoginConfig {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+
+    pub fn profile(&self) -> &str {
+        self.profile.as_deref().unwrap_or("default")
+    }
+
+    pub fn sts_endpoint(&self) -> &str {
+        self.sts_endpoint
+            .as_deref()
+            .unwrap_or("https://sts.amazonaws.com")
+    }
+
+    pub fn sts_region(&self) -> &str {
+        self.sts_region.as_deref().unwrap_or("us-east-1")
+    }
+
+    pub fn duration_seconds(&self) -> u32 {
+        self.duration_seconds.unwrap_or(12 * 60 * 60)
+    }
+}
+
+/// How to authenticate with an account.
+///
+/// E.g. in `config.toml`:
+/// ```toml
+/// [accounts.root]
+/// auth = "sso"
+/// profile = "rust-root"
+///
+/// [accounts.docs-rs-staging]
+/// auth = "assume-role"
+/// source = "root"
+/// role_arns = ["arn:aws:iam::123456789012:role/infratk"]
+///
+/// [accounts.fastly]
+/// auth = "secrets"
+/// env.FASTLY_API_KEY = { backend = "1password", item_id = "abc", field = "credential" }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "auth", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum AccountAuth {
+    /// Login with `aws sso login` and use the given profile.
+    Sso { profile: String },
+    /// Session credentials of the IAM user configured in [`LegacyLoginConfig`],
+    /// or the credentials printed by its `credentials_command`.
+    Legacy,
+    /// Assume the roles one after the other, starting from the credentials of the `source` account.
+    AssumeRole {
+        source: String,
+        role_arns: Vec<String>,
+    },
+    /// Google Cloud application default credentials.
+    GcpAdc {
+        /// Credentials file, e.g. of a service account.
+        /// If missing, the one created by `gcloud auth application-default login` is used.
+        credentials_file: Option<Utf8PathBuf>,
+        /// Default project of the Google provider.
+        project: Option<String>,
+    },
+    /// Environment variables read from a file with `KEY=value` lines.
+    /// `~` at the start of the path is expanded to the home directory.
+    EnvFile { path: Utf8PathBuf },
+    /// Environment variables read from secret backends, e.g. API keys.
+    Secrets { env: BTreeMap<String, SecretSource> },
+    /// Don't plan or upgrade the account.
+    Skip,
+}
+
+impl AccountAuth {
+    /// Authentication used for the accounts that aren't in the config.
+    pub fn default_for(account_dir: &str) -> Self {
+        match account_dir {
+            "legacy" => Self::Legacy,
+            "root" => Self::Sso {
+                profile: "rust-root".to_string(),
+            },
+            account_dir => Self::Sso {
+                profile: account_dir.to_string(),
+            },
+        }
+    }
+}
+
+impl Config {
+    pub fn legacy_mfa(&self) -> Option<SecretSource> {
+        self.legacy_mfa.clone().or_else(|| {
+            self.op_legacy_item_id
+                .clone()
+                .map(|item_id| SecretSource::OnePassword {
+                    item_id,
+                    field: None,
+                })
+        })
+    }
+
+    pub fn account_auth(&self, account_dir: &str) -> AccountAuth {
+        self.accounts
+            .get(account_dir)
+            .cloned()
+            .unwrap_or_else(|| AccountAuth::default_for(account_dir))
+    }
 }
 
 /// Create config dir if it doesn't exist.
@@ -51,3 +205,71 @@ fn get_config(config_file: &Path) -> anyhow::Result<Config> {
     };
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounts_auth_is_parsed() {
+        let config: Config = toml::from_str(
+            r#"
+[accounts.root]
+auth = "sso"
+profile = "admin"
+
+[accounts.dev]
+auth = "assume-role"
+source = "root"
+role_arns = ["arn:aws:iam::123456789012:role/infratk"]
+
+[accounts.old]
+auth = "skip"
+
+[accounts.fastly]
+auth = "secrets"
+env.FASTLY_API_KEY = { backend = "env", var = "MY_FASTLY_KEY" }
+
+[legacy_login]
+credentials_command = ["aws-creds.py", "--profile", "legacy"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.account_auth("root"),
+            AccountAuth::Sso {
+                profile: "admin".to_string()
+            }
+        );
+        assert_eq!(
+            config.account_auth("dev"),
+            AccountAuth::AssumeRole {
+                source: "root".to_string(),
+                role_arns: vec!["arn:aws:iam::123456789012:role/infratk".to_string()],
+            }
+        );
+        assert_eq!(config.account_auth("old"), AccountAuth::Skip);
+        assert_eq!(
+            config.account_auth("fastly"),
+            AccountAuth::Secrets {
+                env: BTreeMap::from([(
+                    "FASTLY_API_KEY".to_string(),
+                    SecretSource::Env {
+                        var: "MY_FASTLY_KEY".to_string()
+                    }
+                )])
+            }
+        );
+        assert_eq!(config.account_auth("legacy"), AccountAuth::Legacy);
+        assert_eq!(
+            config.account_auth("crates-io"),
+            AccountAuth::Sso {
+                profile: "crates-io".to_string()
+            }
+        );
+        assert_eq!(
+            config.legacy_login.credentials_command.unwrap(),
+            ["aws-creds.py", "--profile", "legacy"]
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use camino::{Utf8Path, Utf8PathBuf};
 
 pub fn current_dir() -> Utf8PathBuf {
@@ -23,3 +25,14 @@ pub fn get_stripped_parent(path: &Utf8PathBuf) -> Utf8PathBuf {
     let parent = path.parent().unwrap();
     strip_current_dir(parent)
 }
+
+/// Create cache dir if it doesn't exist.
+/// Return cache dir path.
+pub fn cache_dir() -> PathBuf {
+    let home_dir = home::home_dir().unwrap();
+    let cache_dir = home_dir.join(".cache").join("infratk");
+    if !cache_dir.exists() {
+        fs_err::create_dir_all(&cache_dir).unwrap();
+    }
+    cache_dir
+}
@@ -1,41 +1,128 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use camino_tempfile::Utf8TempDir;
 use git_cmd::Repo;
 
-use crate::{cmd::Cmd, dir};
+use crate::{dir, github::PullRequest};
 
-pub fn assert_current_branch_is_same_as_pr(pr: &str) {
-    let current_branch = get_current_branch();
-    let pr_branch = get_pr_branch(pr);
-    assert_eq!(
-        current_branch, pr_branch,
-        "You are not in the same branch as the PR locally"
+/// A git worktree in a temporary directory.
+/// When dropped, the worktree is removed and the previous current directory is restored.
+pub struct Worktree {
+    repo: Repo,
+    path: Utf8PathBuf,
+    /// Unique parent of `path`, so that concurrent runs don't share the worktree.
+    /// Removed after the worktree.
+    _temp_dir: Utf8TempDir,
+    /// Current directory before entering the worktree.
+    previous_dir: Option<Utf8PathBuf>,
+}
+
+impl Worktree {
+    /// Create a worktree of `repo` with `commit` checked out.
+    /// The worktree directory is named `simpleinfra`, so that infratk commands work in it.
+    pub fn add(repo: Repo, name: &str, commit: &str) -> Self {
+        let temp_dir = camino_tempfile::Builder::new()
+            .prefix(&format!("infratk-{name}-"))
+            .tempdir()
+            .unwrap();
+        let path = temp_dir.path().join("simpleinfra");
+        repo.git(&["worktree", "add", "--detach", path.as_str(), commit])
+            .unwrap();
+        Self {
+            repo,
+            path,
+            _temp_dir: temp_dir,
+            previous_dir: None,
+        }
+    }
+
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    /// Repository the worktree was created from. It shares its objects with the worktree.
+    pub fn repo(&self) -> &Repo {
+        &self.repo
+    }
+
+    /// Run a git command in the worktree.
+    /// [`Repo`] can't be used, because it fails if no branch is checked out.
+    pub fn git(&self, args: &[&str]) -> anyhow::Result<String> {
+        git_cmd::git_in_dir(&self.path, args)
+    }
+
+    /// Set the worktree as the current directory.
+    pub fn enter(&mut self) {
+        self.previous_dir = Some(dir::current_dir());
+        std::env::set_current_dir(&self.path).unwrap();
+    }
+}
+
+impl Drop for Worktree {
+    fn drop(&mut self) {
+        if let Some(previous_dir) = &self.previous_dir {
+            std::env::set_current_dir(previous_dir).unwrap();
+        }
+        remove_worktree(&self.repo, &self.path);
+    }
+}
+
+fn remove_worktree(repo: &Repo, path: &Utf8Path) {
+    if let Err(err) = repo.git(&["worktree", "remove", "--force", path.as_str()]) {
+        tracing::warn!("Could not remove worktree {path}: {err:?}");
+    }
+    if path.exists() {
+        fs_err::remove_dir_all(path).unwrap();
+    }
+    repo.git(&["worktree", "prune"]).unwrap();
+}
+
+/// Fetch the head of the PR and return its commit hash.
+/// Fails if the head isn't the commit returned by GitHub, e.g. because it was pushed meanwhile.
+pub fn fetch_pr_head(repo: &Repo, pr: &PullRequest) -> anyhow::Result<String> {
+    repo.git(&["fetch", &pr.clone_url, &format!("refs/pull/{}/head", pr.number)])?;
+    let head = repo.git(&["rev-parse", "FETCH_HEAD"])?;
+    anyhow::ensure!(
+        head == pr.head_sha,
+        "the head of PR #{} changed while running: expected {}, fetched {head}. Run the command again",
+        pr.number,
+        pr.head_sha
     );
+    Ok(head)
+}
+
+/// Fetch a branch from `url` and return the commit hash of its head.
+pub fn fetch_branch(repo: &Repo, url: &str, branch: &str) -> String {
+    repo.git(&["fetch", url, &format!("refs/heads/{branch}")])
+        .unwrap();
+    repo.git(&["rev-parse", "FETCH_HEAD"]).unwrap()
 }
 
-fn get_current_branch() -> String {
-    Cmd::new("git", ["rev-parse", "--abbrev-ref", "HEAD"])
-        .hide_stdout()
-        .run()
-        .stdout()
-        .trim()
-        .to_string()
-}
-
-fn get_pr_branch(pr: &str) -> String {
-    let output = Cmd::new(
-        "gh",
-        [
-            "pr",
-            "view",
-            pr,
-            "--json",
-            "headRefName",
-            "-q",
-            ".headRefName",
-        ],
-    )
-    .hide_stdout()
-    .run();
-    output.stdout().trim().to_string()
+/// Files changed in the given revision range, e.g. `main..HEAD`.
+/// Renamed files are listed with both their old and new path.
+pub fn diff_files(repo: &Repo, range: &str) -> Vec<Utf8PathBuf> {
+    repo.git(&["diff", "--name-only", "--no-renames", range])
+        .unwrap()
+        .lines()
+        .map(Utf8PathBuf::from)
+        .collect()
+}
+
+/// Files changed in the index, i.e. the staged changes.
+pub fn staged_files(repo: &Repo) -> Vec<Utf8PathBuf> {
+    diff_files(repo, "--cached")
+}
+
+/// Files changed by a single commit.
+/// Merge commits are compared with their first parent, i.e. the branch they were merged into.
+pub fn commit_files(repo: &Repo, commit: &str) -> Vec<Utf8PathBuf> {
+    let parents = repo.git(&["rev-parse", &format!("{commit}^@")]).unwrap();
+    let output = if parents.is_empty() {
+        // Root commit.
+        repo.git(&["diff-tree", "--no-commit-id", "--name-only", "-r", "--root", commit])
+    } else {
+        repo.git(&["diff", "--name-only", "--no-renames", &format!("{commit}^1"), commit])
+    };
+    output.unwrap().lines().map(Utf8PathBuf::from).collect()
 }
 
 pub fn repo() -> Repo {
@@ -47,3 +134,34 @@ pub fn git_root(repo: &Repo) -> camino::Utf8PathBuf {
     let output = repo.git(&["rev-parse", "--show-toplevel"]).unwrap();
     output.into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_commit_files_are_the_merged_ones() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let mut all_args = vec!["-c", "user.name=infratk", "-c", "user.email=i@example.com"];
+            all_args.extend(args);
+            git_cmd::git_in_dir(dir.path(), &all_args).unwrap()
+        };
+        git(&["init", "--initial-branch", "main"]);
+        git(&["commit", "--allow-empty", "-m", "initial"]);
+        git(&["checkout", "-b", "feature"]);
+        fs_err::write(dir.path().join("feature.tf"), "").unwrap();
+        git(&["add", "feature.tf"]);
+        git(&["commit", "-m", "feature"]);
+        git(&["checkout", "main"]);
+        fs_err::write(dir.path().join("main.tf"), "").unwrap();
+        git(&["add", "main.tf"]);
+        git(&["commit", "-m", "main"]);
+        git(&["merge", "--no-ff", "--no-edit", "feature"]);
+
+        let repo = Repo::new(dir.path()).unwrap();
+        assert_eq!(commit_files(&repo, "HEAD"), vec![Utf8PathBuf::from("feature.tf")]);
+        assert_eq!(commit_files(&repo, "HEAD^1"), vec![Utf8PathBuf::from("main.tf")]);
+        assert!(commit_files(&repo, "HEAD^1^1").is_empty());
+    }
+}
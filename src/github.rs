@@ -0,0 +1,487 @@
+use anyhow::Context as _;
+use camino::Utf8PathBuf;
+use secrecy::{ExposeSecret as _, SecretString};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{config::Config, git};
+
+const DEFAULT_API_URL: &str = "https://api.github.com";
+const PER_PAGE: usize = 100;
+
+/// Client for the GitHub REST API.
+pub struct GitHubClient {
+    client: reqwest::Client,
+    api_url: String,
+    token: SecretString,
+    /// Repository in the `owner/name` format.
+    repo: String,
+}
+
+/// A PR resolved from a number, URL or branch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullRequest {
+    pub number: u64,
+    pub head_ref: String,
+    pub head_sha: String,
+    pub base_ref: String,
+    pub base_sha: String,
+    /// Clone URL of the repository the PR is opened against.
+    pub clone_url: String,
+    pub files: Vec<ChangedFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ChangedFile {
+    #[serde(rename = "filename")]
+    pub path: Utf8PathBuf,
+    /// E.g. `added`, `modified`, `removed`, `renamed`.
+    pub status: String,
+    /// Path before the file was renamed.
+    pub previous_filename: Option<Utf8PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Comment {
+    pub id: u64,
+    pub body: String,
+    /// Author of the comment. `None` if the account was deleted.
+    pub user: Option<User>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct User {
+    pub login: String,
+}
+
+#[derive(Deserialize)]
+struct PullRequestJson {
+    number: u64,
+    head: BranchJson,
+    base: BranchJson,
+}
+
+#[derive(Deserialize)]
+struct BranchJson {
+    #[serde(rename = "ref")]
+    ref_name: String,
+    sha: String,
+    repo: Option<RepoJson>,
+}
+
+#[derive(Deserialize)]
+struct RepoJson {
+    clone_url: String,
+}
+
+#[derive(Serialize)]
+struct CommentBody<'a> {
+    body: &'a str,
+}
+
+#[derive(Serialize)]
+struct NewPullRequest<'a> {
+    title: &'a str,
+    head: &'a str,
+    base: &'a str,
+    body: &'a str,
+}
+
+#[derive(Deserialize)]
+struct CreatedPullRequest {
+    html_url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Repository {
+    pub default_branch: String,
+    pub clone_url: String,
+}
+
+impl GitHubClient {
+    /// Create a client for the repository of the current directory.
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let api_url = config
+            .github_api_url
+            .clone()
+            .unwrap_or_else(|| DEFAULT_API_URL.to_string());
+        let token = find_token(config, &api_url)?;
+        let repo = match &config.github_repo {
+            Some(repo) => repo.clone(),
+            None => repo_from_remotes()?,
+        };
+        Ok(Self::with_api_url(api_url, token, repo))
+    }
+
+    pub fn with_api_url(api_url: String, token: SecretString, repo: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token,
+            repo,
+        }
+    }
+
+    /// Resolve a PR from its number, URL or head branch.
+    pub async fn get_pr(&self, pr: &str) -> anyhow::Result<PullRequest> {
+        let number = match parse_pr_number(pr) {
+            Some(number) => number,
+            None => self.find_pr_by_branch(pr).await?,
+        };
+        let pr_json: PullRequestJson = self
+            .get(&format!("repos/{}/pulls/{number}", self.repo))
+            .await?;
+        let files = self
+            .get_paginated(&format!("repos/{}/pulls/{number}/files", self.repo))
+            .await?;
+        let clone_url = pr_json
+            .base
+            .repo
+            .context("PR base repository not found")?
+            .clone_url;
+        Ok(PullRequest {
+            number: pr_json.number,
+            head_ref: pr_json.head.ref_name,
+            head_sha: pr_json.head.sha,
+            base_ref: pr_json.base.ref_name,
+            base_sha: pr_json.base.sha,
+            clone_url,
+            files,
+        })
+    }
+
+    /// `branch` can be prefixed with the owner of the fork, e.g. `owner:branch`.
+    async fn find_pr_by_branch(&self, branch: &str) -> anyhow::Result<u64> {
+        let head = if branch.contains(':') {
+            branch.to_string()
+        } else {
+            let owner = self.repo.split('/').next().unwrap_or_default();
+            format!("{owner}:{branch}")
+        };
+        let query = query_string(&[("head", &head), ("state", "open")]);
+        let prs: Vec<PullRequestJson> = self
+            .get(&format!("repos/{}/pulls?{query}", self.repo))
+            .await?;
+        prs.first()
+            .map(|pr| pr.number)
+            .with_context(|| format!("no open PR found for branch `{head}`"))
+    }
+
+    /// User authenticated with the token.
+    pub async fn current_user(&self) -> anyhow::Result<User> {
+        self.get("user").await
+    }
+
+    pub async fn list_comments(&self, pr_number: u64) -> anyhow::Result<Vec<Comment>> {
+        self.get_paginated(&format!("repos/{}/issues/{pr_number}/comments", self.repo))
+            .await
+    }
+
+    pub async fn create_comment(&self, pr_number: u64, body: &str) -> anyhow::Result<()> {
+        let url = format!("repos/{}/issues/{pr_number}/comments", self.repo);
+        self.send(self.request(reqwest::Method::POST, &url).json(&CommentBody { body }))
+            .await
+    }
+
+    pub async fn update_comment(&self, comment_id: u64, body: &str) -> anyhow::Result<()> {
+        let url = format!("repos/{}/issues/comments/{comment_id}", self.repo);
+        self.send(self.request(reqwest::Method::PATCH, &url).json(&CommentBody { body }))
+            .await
+    }
+
+    pub async fn delete_comment(&self, comment_id: u64) -> anyhow::Result<()> {
+        let url = format!("repos/{}/issues/comments/{comment_id}", self.repo);
+        self.send(self.request(reqwest::Method::DELETE, &url)).await
+    }
+
+    pub async fn get_repository(&self) -> anyhow::Result<Repository> {
+        self.get(&format!("repos/{}", self.repo)).await
+    }
+
+    /// Open a PR from `branch` of the `origin` git remote to the default branch of the repository.
+    /// Returns the URL of the PR.
+    pub async fn create_pr(&self, branch: &str, title: &str, body: &str) -> anyhow::Result<String> {
+        let repository = self.get_repository().await?;
+        let origin_url = git::repo().git(&["remote", "get-url", "origin"])?;
+        let head = match repo_from_url(&origin_url) {
+            // The branch was pushed to a fork.
+            Some(origin) if origin != self.repo => {
+                let owner = origin.split('/').next().unwrap_or_default();
+                format!("{owner}:{branch}")
+            }
+            _ => branch.to_string(),
+        };
+        let new_pr = NewPullRequest {
+            title,
+            head: &head,
+            base: &repository.default_branch,
+            body,
+        };
+        let url = format!("repos/{}/pulls", self.repo);
+        let created: CreatedPullRequest = self
+            .request(reqwest::Method::POST, &url)
+            .json(&new_pr)
+            .send()
+            .await?
+            .error_for_status()
+            .context("failed to open PR")?
+            .json()
+            .await?;
+        Ok(created.html_url)
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}/{path}", self.api_url))
+            .bearer_auth(self.token.expose_secret())
+            .header(reqwest::header::USER_AGENT, "infratk")
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> anyhow::Result<()> {
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<T> {
+        let response = self
+            .request(reqwest::Method::GET, path)
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("GitHub request to {path} failed"))?;
+        Ok(response.json().await?)
+    }
+
+    /// Get all the pages of a list.
+    async fn get_paginated<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<Vec<T>> {
+        let separator = if path.contains('?') { '&' } else { '?' };
+        let mut items = vec![];
+        for page in 1.. {
+            let page_items: Vec<T> = self
+                .get(&format!("{path}{separator}per_page={PER_PAGE}&page={page}"))
+                .await?;
+            let is_last_page = page_items.len() < PER_PAGE;
+            items.extend(page_items);
+            if is_last_page {
+                break;
+            }
+        }
+        Ok(items)
+    }
+}
+
+/// Encode the parameters as a URL query, e.g. `head=owner%3Abranch&state=open`.
+fn query_string(params: &[(&str, &str)]) -> String {
+    let mut url = reqwest::Url::parse("http://localhost").unwrap();
+    url.query_pairs_mut().extend_pairs(params);
+    url.query().unwrap_or_default().to_string()
+}
+
+/// Parse a PR number or a PR URL like `https://github.com/owner/repo/pull/123`.
+fn parse_pr_number(pr: &str) -> Option<u64> {
+    let number = match pr.split_once("/pull/") {
+        Some((_, rest)) => rest.split('/').next()?,
+        None => pr,
+    };
+    number.parse().ok()
+}
+
+/// Look for the token in the `GITHUB_TOKEN` environment variable, then in the
+/// GitHub CLI configuration and finally in the infratk configuration.
+fn find_token(config: &Config, api_url: &str) -> anyhow::Result<SecretString> {
+    if let Ok(token) = std::env::var("GITHUB_TOKEN") {
+        return Ok(SecretString::new(token));
+    }
+    let host = if api_url == DEFAULT_API_URL {
+        "github.com".to_string()
+    } else {
+        // GitHub Enterprise, e.g. `https://github.example.com/api/v3`.
+        api_url
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    };
+    if let Some(token) = gh_hosts_file()
+        .and_then(|f| fs_err::read_to_string(f).ok())
+        .and_then(|content| token_from_gh_hosts(&content, &host))
+    {
+        return Ok(token);
+    }
+    config
+        .github_token
+        .clone()
+        .map(SecretString::new)
+        .context("GitHub token not found. Set the `GITHUB_TOKEN` environment variable, login with `gh auth login` or set `github_token` in the infratk config")
+}
+
+fn gh_hosts_file() -> Option<Utf8PathBuf> {
+    let config_dir = match std::env::var("GH_CONFIG_DIR") {
+        Ok(dir) => Utf8PathBuf::from(dir),
+        Err(_) => {
+            let home_dir = Utf8PathBuf::from_path_buf(home::home_dir()?).ok()?;
+            home_dir.join(".config").join("gh")
+        }
+    };
+    Some(config_dir.join("hosts.yml"))
+}
+
+/// Read the `oauth_token` of `host` from the `hosts.yml` file of the GitHub CLI.
+/// The token is missing if the GitHub CLI stores it in the system keyring.
+fn token_from_gh_hosts(content: &str, host: &str) -> Option<SecretString> {
+    let mut in_host = false;
+    for line in content.lines() {
+        if !line.starts_with(' ') {
+            in_host = line.trim_end() == format!("{host}:");
+            continue;
+        }
+        if in_host {
+            if let Some(token) = line.trim().strip_prefix("oauth_token:") {
+                return Some(SecretString::new(token.trim().to_string()));
+            }
+        }
+    }
+    None
+}
+
+/// Get the `owner/name` of the repository from the `upstream` or `origin` git remote.
+fn repo_from_remotes() -> anyhow::Result<String> {
+    let repo = git::repo();
+    for remote in ["upstream", "origin"] {
+        if let Ok(url) = repo.git(&["remote", "get-url", remote]) {
+            if let Some(repo) = repo_from_url(&url) {
+                return Ok(repo);
+            }
+        }
+    }
+    anyhow::bail!("can't find the GitHub repository from the git remotes. Set `github_repo` in the infratk config")
+}
+
+/// E.g. `git@github.com:owner/repo.git` or `https://github.com/owner/repo` -> `owner/repo`.
+fn repo_from_url(url: &str) -> Option<String> {
+    let path = url
+        .trim()
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .rsplit(['/', ':'])
+        .take(2)
+        .collect::<Vec<_>>();
+    match path.as_slice() {
+        [name, owner] if !name.is_empty() && !owner.is_empty() => Some(format!("{owner}/{name}")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        io::{BufRead as _, BufReader, Write as _},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::*;
+
+    /// Serve `responses` in order, one per request.
+    /// Returns the URL of the server.
+    pub(crate) fn mock_server(responses: Vec<(&'static str, String)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for (expected_path, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                // Read the headers.
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let status = if request_line.contains(expected_path) {
+                    "200 OK"
+                } else {
+                    "404 Not Found"
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn pr_is_resolved_from_branch() {
+        let pr = r#"{
+            "number": 42,
+            "head": {"ref": "my-branch", "sha": "abc", "repo": null},
+            "base": {"ref": "master", "sha": "def", "repo": {"clone_url": "https://github.com/rust-lang/simpleinfra.git"}}
+        }"#;
+        let url = mock_server(vec![
+            (
+                "/repos/rust-lang/simpleinfra/pulls?head=rust-lang%3Amy-branch&state=open",
+                format!("[{pr}]"),
+            ),
+            ("/repos/rust-lang/simpleinfra/pulls/42 ", pr.to_string()),
+            (
+                "/repos/rust-lang/simpleinfra/pulls/42/files",
+                r#"[
+                    {"filename": "terragrunt/accounts/dev/app/terragrunt.hcl", "status": "removed"},
+                    {"filename": "terragrunt/accounts/dev/new/terragrunt.hcl", "status": "renamed", "previous_filename": "terragrunt/accounts/dev/old/terragrunt.hcl"}
+                ]"#
+                .to_string(),
+            ),
+        ]);
+        let client = GitHubClient::with_api_url(
+            url,
+            SecretString::new("token".to_string()),
+            "rust-lang/simpleinfra".to_string(),
+        );
+        let pr = client.get_pr("my-branch").await.unwrap();
+        assert_eq!(
+            pr,
+            PullRequest {
+                number: 42,
+                head_ref: "my-branch".to_string(),
+                head_sha: "abc".to_string(),
+                base_ref: "master".to_string(),
+                base_sha: "def".to_string(),
+                clone_url: "https://github.com/rust-lang/simpleinfra.git".to_string(),
+                files: vec![
+                    ChangedFile {
+                        path: "terragrunt/accounts/dev/app/terragrunt.hcl".into(),
+                        status: "removed".to_string(),
+                        previous_filename: None,
+                    },
+                    ChangedFile {
+                        path: "terragrunt/accounts/dev/new/terragrunt.hcl".into(),
+                        status: "renamed".to_string(),
+                        previous_filename: Some("terragrunt/accounts/dev/old/terragrunt.hcl".into()),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn token_is_read_from_gh_hosts() {
+        let content = "\
+github.example.com:
+    oauth_token: other
+github.com:
+    user: me
+    oauth_token: gho_123
+    git_protocol: ssh
+";
+        let token = token_from_gh_hosts(content, "github.com").unwrap();
+        assert_eq!(token.expose_secret(), "gho_123");
+        assert!(token_from_gh_hosts("github.com:\n    user: me\n", "github.com").is_none());
+    }
+}
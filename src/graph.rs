@@ -1,22 +1,60 @@
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    fmt,
+};
 
 use camino::{Utf8Path, Utf8PathBuf};
 use tracing::{debug, warn};
 
-use petgraph::{graph::NodeIndex, visit::Bfs, Graph};
+use petgraph::{
+    algo::tarjan_scc,
+    graph::{EdgeIndex, NodeIndex},
+    visit::{Bfs, EdgeRef as _, Reversed},
+    Graph,
+};
 
-use crate::{dir, LOCKFILE};
+use crate::{
+    dir,
+    graph_cache::{GraphCache, RawDependency},
+    LOCKFILE,
+};
 
 /// A graph of terragrunt and terraform modules.
+/// An edge goes from a module to one of its dependencies.
 pub struct ModulesGraph {
-    pub graph: Graph<Utf8PathBuf, i32>,
+    pub graph: Graph<Utf8PathBuf, Declaration>,
+    /// Collection of `module` - `graph index`.
+    indices: HashMap<Utf8PathBuf, NodeIndex>,
+}
+
+/// Where a dependency was declared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    pub file: Utf8PathBuf,
+    /// 1-based line number.
+    pub line: usize,
+}
+
+impl fmt::Display for Declaration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// A step of a dependency chain: `module` depends on `dependency`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub module: Utf8PathBuf,
+    pub dependency: Utf8PathBuf,
+    pub declaration: Declaration,
 }
 
 impl ModulesGraph {
     pub fn new(outdated_packages: Option<&BTreeSet<Utf8PathBuf>>) -> Self {
-        let mut graph: Graph<Utf8PathBuf, i32> = Graph::new();
+        let mut graph: Graph<Utf8PathBuf, Declaration> = Graph::new();
         // Collection of `file` - `graph index`.
         let mut indices = HashMap::<Utf8PathBuf, NodeIndex>::new();
+        let mut cache = GraphCache::load();
         let files = get_all_tf_and_hcl_files();
         for f in files {
             let f_parent = dir::get_stripped_parent(&f);
@@ -24,17 +62,31 @@ impl ModulesGraph {
                 .get(&f_parent)
                 .cloned()
                 .unwrap_or_else(|| add_node(&mut graph, f_parent, &mut indices, outdated_packages));
-            let dependencies = get_dependencies(&f);
-            for d in dependencies {
+            let dependencies = get_dependencies(&f, &mut cache);
+            for (d, declaration) in dependencies {
                 let d_index = indices
                     .get(&d)
                     .cloned()
                     .unwrap_or_else(|| add_node(&mut graph, d, &mut indices, outdated_packages));
 
-                graph.update_edge(node_index, d_index, 0);
+                // Keep the first declaration of the dependency.
+                if graph.find_edge(node_index, d_index).is_none() {
+                    graph.add_edge(node_index, d_index, declaration);
+                }
             }
         }
-        Self { graph }
+        cache.save();
+        Self { graph, indices }
+    }
+
+    /// Create a graph where nodes aren't decorated with emojis.
+    #[cfg(test)]
+    pub fn from_graph(graph: Graph<Utf8PathBuf, Declaration>) -> Self {
+        let indices = graph
+            .node_indices()
+            .map(|i| (graph[i].clone(), i))
+            .collect();
+        Self { graph, indices }
     }
 
     pub fn get_dependent_modules_containing_lockfile<T>(&self, modules: &[T]) -> Vec<Utf8PathBuf>
@@ -62,18 +114,15 @@ impl ModulesGraph {
     }
 
     pub fn get_dependent_modules_of_dir(&self, module: &Utf8Path) -> Vec<Utf8PathBuf> {
-        let module_index = self
-            .graph
-            .node_indices()
-            .find(|i| self.graph[*i] == module)
-            .expect("module not found in graph");
+        let module_index = self.node_index(module).expect("module not found in graph");
         let mut dependent_modules = vec![];
 
-        let inverted_graph = self.invert_graph();
-        let mut bfs = Bfs::new(&inverted_graph, module_index);
+        // Walk the edges backwards, without copying the graph.
+        let inverted_graph = Reversed(&self.graph);
+        let mut bfs = Bfs::new(inverted_graph, module_index);
 
-        while let Some(nx) = bfs.next(&inverted_graph) {
-            let dep = inverted_graph[nx].clone();
+        while let Some(nx) = bfs.next(inverted_graph) {
+            let dep = self.graph[nx].clone();
             debug!("Found dependent module: {:?}", dep);
             dependent_modules.push(dep);
         }
@@ -81,22 +130,168 @@ impl ModulesGraph {
         dependent_modules
     }
 
-    fn invert_graph(&self) -> Graph<Utf8PathBuf, i32> {
-        let mut inverted_graph = Graph::new();
-        let mut node_map = HashMap::new();
+    /// Shortest chain of dependencies through which `module` depends on `dependency`.
+    /// The first link starts from `module` and the last one ends in `dependency`.
+    /// Returns `None` if `module` doesn't depend on `dependency`.
+    pub fn dependency_chain(&self, module: &Utf8Path, dependency: &Utf8Path) -> Option<Vec<Link>> {
+        let start = self.node_index(module)?;
+        let end = self.node_index(dependency)?;
+        // For every visited node, the edge we used to reach it.
+        let mut reached_by = HashMap::<NodeIndex, EdgeIndex>::new();
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(node) = queue.pop_front() {
+            if node == end {
+                break;
+            }
+            for edge in self.graph.edges(node) {
+                if visited.insert(edge.target()) {
+                    reached_by.insert(edge.target(), edge.id());
+                    queue.push_back(edge.target());
+                }
+            }
+        }
+        if !visited.contains(&end) {
+            return None;
+        }
 
-        for node_index in self.graph.node_indices() {
-            let node = &self.graph[node_index];
-            let new_node_index = inverted_graph.add_node(node.clone());
-            node_map.insert(node_index, new_node_index);
+        let mut chain = vec![];
+        let mut node = end;
+        while let Some(edge) = reached_by.get(&node) {
+            let link = self.link(*edge);
+            node = self.graph.edge_endpoints(*edge).unwrap().0;
+            chain.push(link);
         }
+        chain.reverse();
+        Some(chain)
+    }
 
-        for edge in self.graph.edge_indices() {
-            let (source, target) = self.graph.edge_endpoints(edge).unwrap();
-            inverted_graph.add_edge(node_map[&target], node_map[&source], 0);
+    /// Group `modules` in waves that can be applied one after the other:
+    /// modules only depend on modules of the previous waves.
+    /// Modules of the same wave are sorted alphabetically.
+    /// Dependencies that pass through modules not included in `modules` are considered too.
+    pub fn apply_waves<T>(&self, modules: &[T]) -> anyhow::Result<Vec<Vec<Utf8PathBuf>>>
+    where
+        T: AsRef<Utf8Path>,
+    {
+        let modules: Vec<&Utf8Path> = modules.iter().map(|m| m.as_ref()).collect();
+        // For every module, the other modules in `modules` it depends on.
+        let mut pending: Vec<(&Utf8Path, Vec<&Utf8Path>)> = modules
+            .iter()
+            .map(|&m| {
+                let dependencies = self.get_dependencies_of_dir(m);
+                let pending_dependencies = modules
+                    .iter()
+                    .filter(|&&other| {
+                        other != m && dependencies.contains(&dir::strip_current_dir(other))
+                    })
+                    .copied()
+                    .collect();
+                (m, pending_dependencies)
+            })
+            .collect();
+
+        let mut waves = vec![];
+        while !pending.is_empty() {
+            let (mut wave, rest): (Vec<_>, Vec<_>) = pending
+                .into_iter()
+                .partition(|(_, dependencies)| dependencies.is_empty());
+            if wave.is_empty() {
+                let modules_in_cycle: Vec<_> = rest.iter().map(|(m, _)| m).collect();
+                anyhow::bail!("dependency cycle between modules {modules_in_cycle:?}");
+            }
+            wave.sort();
+            let wave: Vec<&Utf8Path> = wave.into_iter().map(|(m, _)| m).collect();
+            pending = rest
+                .into_iter()
+                .map(|(m, mut dependencies)| {
+                    dependencies.retain(|d| !wave.contains(d));
+                    (m, dependencies)
+                })
+                .collect();
+            waves.push(wave.into_iter().map(|m| m.to_path_buf()).collect());
+        }
+        Ok(waves)
+    }
+
+    /// Sort `modules` so that every module comes after the modules it depends on.
+    /// If there's a dependency cycle, `modules` are returned in the original order.
+    pub fn sort_by_dependencies<T>(&self, modules: &[T]) -> Vec<Utf8PathBuf>
+    where
+        T: AsRef<Utf8Path>,
+    {
+        match self.apply_waves(modules) {
+            Ok(waves) => waves.into_iter().flatten().collect(),
+            Err(err) => {
+                warn!("Can't sort modules by dependencies: {err:?}");
+                modules.iter().map(|m| m.as_ref().to_path_buf()).collect()
+            }
         }
+    }
 
-        inverted_graph
+    /// Modules that `module` depends on, directly or transitively.
+    /// Paths are relative to the current directory.
+    pub fn get_dependencies_of_dir(&self, module: &Utf8Path) -> HashSet<Utf8PathBuf> {
+        let Some(module_index) = self.node_index(&dir::strip_current_dir(module)) else {
+            return HashSet::new();
+        };
+        let mut dependencies = HashSet::new();
+        let mut bfs = Bfs::new(&self.graph, module_index);
+        while let Some(nx) = bfs.next(&self.graph) {
+            if nx != module_index {
+                dependencies.insert(self.graph[nx].clone());
+            }
+        }
+        dependencies
+    }
+
+    pub fn contains(&self, module: &Utf8Path) -> bool {
+        self.node_index(module).is_some()
+    }
+
+    /// All the dependencies of the graph.
+    pub fn links(&self) -> Vec<Link> {
+        self.graph.edge_indices().map(|e| self.link(e)).collect()
+    }
+
+    /// Dependency cycles of the graph.
+    /// Every cycle starts and ends with the same module.
+    pub fn cycles(&self) -> Vec<Vec<Link>> {
+        let mut cycles = vec![];
+        for component in tarjan_scc(&self.graph) {
+            let start = component[0];
+            // Pick a dependency of `start` that is part of the cycle.
+            // If the component has only one node, this is a self-loop.
+            let Some(edge) = self
+                .graph
+                .edges(start)
+                .find(|e| component.contains(&e.target()))
+            else {
+                continue;
+            };
+            let mut cycle = vec![self.link(edge.id())];
+            if edge.target() != start {
+                let back_to_start = self
+                    .dependency_chain(&self.graph[edge.target()], &self.graph[start])
+                    .expect("nodes of a strongly connected component reach each other");
+                cycle.extend(back_to_start);
+            }
+            cycles.push(cycle);
+        }
+        cycles
+    }
+
+    fn link(&self, edge: EdgeIndex) -> Link {
+        let (source, target) = self.graph.edge_endpoints(edge).unwrap();
+        Link {
+            module: self.graph[source].clone(),
+            dependency: self.graph[target].clone(),
+            declaration: self.graph[edge].clone(),
+        }
+    }
+
+    fn node_index(&self, module: &Utf8Path) -> Option<NodeIndex> {
+        self.indices.get(module).copied()
     }
 }
 
@@ -115,7 +310,7 @@ fn remove_duplicates(modules: Vec<Utf8PathBuf>) -> Vec<Utf8PathBuf> {
 }
 
 fn add_node(
-    graph: &mut Graph<Utf8PathBuf, i32>,
+    graph: &mut Graph<Utf8PathBuf, Declaration>,
     dir: Utf8PathBuf,
     indices: &mut HashMap<Utf8PathBuf, NodeIndex>,
     outdated_packages: Option<&BTreeSet<Utf8PathBuf>>,
@@ -141,22 +336,41 @@ fn add_node(
     node_index
 }
 
-/// Get the dependencies of a file
-/// Dependencies are anything in the file like `source = "path"` or `config_path = "path"`.
-fn get_dependencies(file: &Utf8Path) -> Vec<Utf8PathBuf> {
-    let content = std::fs::read_to_string(file).expect("could not read file");
+/// Get the dependencies of a file, together with the place where they are declared.
+/// The file is parsed only if it changed since it was cached.
+fn get_dependencies(file: &Utf8Path, cache: &mut GraphCache) -> Vec<(Utf8PathBuf, Declaration)> {
+    let raw_dependencies = cache
+        .get_or_insert_with(file, parse_dependencies)
+        .expect("could not read file");
     let mut dependencies = vec![];
-    for line in content.lines() {
-        if let Some(dependency) = get_dependency_from_line(line) {
-            let module_path = file.parent().unwrap().join(dependency);
-            let relative_path = get_relative_path(&module_path);
-            debug!("found dependency {:?} from line {line}", relative_path);
-            dependencies.push(relative_path);
-        }
+    for RawDependency { line, path } in raw_dependencies {
+        // Resolve paths every time, because the directories they point to might have changed.
+        let module_path = file.parent().unwrap().join(path);
+        let relative_path = get_relative_path(&module_path);
+        debug!("found dependency {:?} at line {line}", relative_path);
+        let declaration = Declaration {
+            file: dir::strip_current_dir(file),
+            line,
+        };
+        dependencies.push((relative_path, declaration));
     }
     dependencies
 }
 
+/// Dependencies are anything in the file like `source = "path"` or `config_path = "path"`.
+fn parse_dependencies(content: &str) -> Vec<RawDependency> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(line_index, line)| {
+            get_dependency_from_line(line).map(|dependency| RawDependency {
+                line: line_index + 1,
+                path: dependency.to_string(),
+            })
+        })
+        .collect()
+}
+
 /// Get all the files that might contain a dependency
 pub fn get_all_tf_and_hcl_files() -> Vec<Utf8PathBuf> {
     let mut files = vec![];
@@ -229,7 +443,100 @@ mod tests {
                         source = "../aaaa"
                 "#;
         fs_err::write(file.path(), content).unwrap();
-        let dependencies = get_dependencies(file.path());
+        let mut cache = GraphCache::default();
+        let dependencies = get_dependencies(file.path(), &mut cache);
         assert_eq!(dependencies.len(), 1);
+        assert_eq!(dependencies[0].1.line, 2);
+    }
+
+    fn declaration(file: &str) -> Declaration {
+        Declaration {
+            file: Utf8PathBuf::from(file),
+            line: 1,
+        }
+    }
+
+    #[test]
+    fn shortest_dependency_chain_is_found() {
+        let mut graph = Graph::new();
+        let stack = graph.add_node(Utf8PathBuf::from("stack"));
+        let a = graph.add_node(Utf8PathBuf::from("a"));
+        let b = graph.add_node(Utf8PathBuf::from("b"));
+        let c = graph.add_node(Utf8PathBuf::from("c"));
+        // stack -> a -> b -> c
+        // stack -> c
+        graph.add_edge(stack, a, declaration("stack/terragrunt.hcl"));
+        graph.add_edge(a, b, declaration("a/main.tf"));
+        graph.add_edge(b, c, declaration("b/main.tf"));
+        graph.add_edge(stack, c, declaration("stack/main.tf"));
+        let graph = ModulesGraph::from_graph(graph);
+
+        let chain = graph
+            .dependency_chain(Utf8Path::new("stack"), Utf8Path::new("c"))
+            .unwrap();
+        assert_eq!(
+            chain,
+            vec![Link {
+                module: "stack".into(),
+                dependency: "c".into(),
+                declaration: declaration("stack/main.tf"),
+            }]
+        );
+        let chain = graph
+            .dependency_chain(Utf8Path::new("a"), Utf8Path::new("c"))
+            .unwrap();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[1].declaration, declaration("b/main.tf"));
+        assert!(graph
+            .dependency_chain(Utf8Path::new("c"), Utf8Path::new("stack"))
+            .is_none());
+    }
+
+    #[test]
+    fn cycles_are_detected() {
+        let mut graph = Graph::new();
+        let a = graph.add_node(Utf8PathBuf::from("a"));
+        let b = graph.add_node(Utf8PathBuf::from("b"));
+        let c = graph.add_node(Utf8PathBuf::from("c"));
+        let d = graph.add_node(Utf8PathBuf::from("d"));
+        // a -> b -> c -> a
+        // c -> d
+        graph.add_edge(a, b, declaration("a/main.tf"));
+        graph.add_edge(b, c, declaration("b/main.tf"));
+        graph.add_edge(c, a, declaration("c/main.tf"));
+        graph.add_edge(c, d, declaration("c/main.tf"));
+        let graph = ModulesGraph::from_graph(graph);
+
+        let cycles = graph.cycles();
+        assert_eq!(cycles.len(), 1);
+        let cycle = &cycles[0];
+        assert_eq!(cycle.len(), 3);
+        assert_eq!(cycle.first().unwrap().module, cycle.last().unwrap().dependency);
+    }
+
+    #[test]
+    fn modules_are_grouped_in_waves() {
+        let mut graph = Graph::new();
+        let vpc = graph.add_node(Utf8PathBuf::from("vpc"));
+        let module = graph.add_node(Utf8PathBuf::from("module"));
+        let db = graph.add_node(Utf8PathBuf::from("db"));
+        let app = graph.add_node(Utf8PathBuf::from("app"));
+        // app -> db -> module -> vpc
+        // app -> vpc
+        graph.add_edge(app, db, declaration("app/terragrunt.hcl"));
+        graph.add_edge(app, vpc, declaration("app/terragrunt.hcl"));
+        graph.add_edge(db, module, declaration("db/terragrunt.hcl"));
+        graph.add_edge(module, vpc, declaration("module/main.tf"));
+        let graph = ModulesGraph::from_graph(graph);
+
+        let waves = graph.apply_waves(&["app", "db", "vpc"]).unwrap();
+        assert_eq!(
+            waves,
+            vec![
+                vec![Utf8PathBuf::from("vpc")],
+                vec![Utf8PathBuf::from("db")],
+                vec![Utf8PathBuf::from("app")],
+            ]
+        );
     }
 }
@@ -0,0 +1,178 @@
+use std::{collections::BTreeMap, path::PathBuf, time::SystemTime};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use tracing::{debug, warn};
+
+use crate::dir;
+
+const CACHE_FILE: &str = "graph.json";
+
+/// Dependencies parsed from terraform and terragrunt files, stored on disk
+/// so that only the files that changed since the last run are parsed again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct GraphCache {
+    /// Absolute path of the file -> dependencies declared in the file.
+    files: BTreeMap<Utf8PathBuf, CachedFile>,
+    /// Where the cache is saved. `None` if the cache is only kept in memory.
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// Whether the cache changed since it was loaded.
+    #[serde(skip)]
+    changed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedFile {
+    /// If the metadata of the file didn't change, the file isn't read at all.
+    metadata: FileMetadata,
+    /// SHA-256 of the file content, hex encoded.
+    /// Used when only the metadata changed, e.g. after a `git checkout`.
+    hash: String,
+    dependencies: Vec<RawDependency>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FileMetadata {
+    modified: SystemTime,
+    size: u64,
+}
+
+impl FileMetadata {
+    fn of(file: &Utf8Path) -> std::io::Result<Self> {
+        let metadata = fs_err::metadata(file)?;
+        Ok(Self {
+            modified: metadata.modified()?,
+            size: metadata.len(),
+        })
+    }
+}
+
+/// A dependency as it's written in the file, e.g. `../../modules/vpc`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawDependency {
+    /// 1-based line number.
+    pub line: usize,
+    pub path: String,
+}
+
+impl GraphCache {
+    /// Load the cache from disk.
+    /// If the cache is missing or invalid, start from an empty one.
+    pub fn load() -> Self {
+        let path = cache_file();
+        let mut cache = match fs_err::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|err| {
+                warn!("Ignoring invalid graph cache {path:?}: {err:?}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+        cache.path = Some(path);
+        cache
+    }
+
+    /// Return the dependencies of `file` from the cache.
+    /// If the file changed since it was cached, parse it again with `parse`.
+    pub fn get_or_insert_with(
+        &mut self,
+        file: &Utf8Path,
+        parse: impl FnOnce(&str) -> Vec<RawDependency>,
+    ) -> std::io::Result<Vec<RawDependency>> {
+        let metadata = FileMetadata::of(file)?;
+        let cached = self.files.get_mut(file);
+        if let Some(cached) = &cached {
+            if cached.metadata == metadata {
+                return Ok(cached.dependencies.clone());
+            }
+        }
+        let content = fs_err::read_to_string(file)?;
+        let hash = hash(&content);
+        self.changed = true;
+        if let Some(cached) = cached {
+            if cached.hash == hash {
+                cached.metadata = metadata;
+                return Ok(cached.dependencies.clone());
+            }
+        }
+        debug!("Parsing dependencies of {file}");
+        let dependencies = parse(&content);
+        self.files.insert(
+            file.to_path_buf(),
+            CachedFile {
+                metadata,
+                hash,
+                dependencies: dependencies.clone(),
+            },
+        );
+        Ok(dependencies)
+    }
+
+    /// Write the cache to disk, removing files that don't exist anymore.
+    pub fn save(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let files_count = self.files.len();
+        self.files.retain(|file, _| file.exists());
+        if !self.changed && self.files.len() == files_count {
+            return;
+        }
+        let content = serde_json::to_string(&self).unwrap();
+        if let Err(err) = fs_err::write(path, content) {
+            warn!("Could not save graph cache: {err:?}");
+        }
+    }
+}
+
+fn hash(content: &str) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn cache_file() -> PathBuf {
+    dir::cache_dir().join(CACHE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_parsed_only_when_changed() {
+        let mut cache = GraphCache::default();
+        let file = camino_tempfile::NamedUtf8TempFile::new().unwrap();
+        let file = file.path();
+        let parse = |content: &str| {
+            vec![RawDependency {
+                line: 1,
+                path: content.to_string(),
+            }]
+        };
+        let parse_unreachable = |_: &str| -> Vec<RawDependency> { panic!("file parsed again") };
+
+        fs_err::write(file, "../a").unwrap();
+        let first = cache.get_or_insert_with(file, parse).unwrap();
+        let cached = cache.get_or_insert_with(file, parse_unreachable).unwrap();
+        assert_eq!(first, cached);
+
+        // Same content, different modification time.
+        let modified = SystemTime::UNIX_EPOCH;
+        std::fs::File::options()
+            .write(true)
+            .open(file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let cached = cache.get_or_insert_with(file, parse_unreachable).unwrap();
+        assert_eq!(first, cached);
+        assert_eq!(cache.files[file].metadata.modified, modified);
+
+        fs_err::write(file, "../bb").unwrap();
+        let changed = cache.get_or_insert_with(file, parse).unwrap();
+        assert_eq!(changed[0].path, "../bb");
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::BTreeSet;
+
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use petgraph::visit::EdgeRef as _;
+use serde::Serialize;
+
+use crate::{graph::ModulesGraph, LOCKFILE};
+
+const VIEWER: &str = include_str!("graph_viewer.html");
+const DATA_PLACEHOLDER: &str = "__GRAPH_DATA__";
+
+#[derive(Serialize)]
+struct HtmlGraph {
+    nodes: Vec<HtmlNode>,
+    edges: Vec<HtmlEdge>,
+}
+
+#[derive(Serialize)]
+struct HtmlNode {
+    path: Utf8PathBuf,
+    /// Account of the terragrunt stack, or `terraform` for modules under the terraform directory.
+    account: Option<String>,
+    /// `None` if the module doesn't contain a lockfile or outdated providers weren't checked.
+    outdated: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct HtmlEdge {
+    /// Index of the module.
+    source: usize,
+    /// Index of the dependency.
+    target: usize,
+    declaration: String,
+}
+
+/// Create a self-contained HTML page that shows the graph.
+pub fn to_html(graph: &ModulesGraph, outdated_packages: Option<&BTreeSet<Utf8PathBuf>>) -> String {
+    let nodes = graph
+        .graph
+        .node_weights()
+        .map(|path| HtmlNode {
+            path: path.clone(),
+            account: account(path),
+            outdated: outdated_packages
+                .filter(|_| path.join(LOCKFILE).exists())
+                .map(|outdated| outdated.contains(path)),
+        })
+        .collect();
+    let edges = graph
+        .graph
+        .edge_references()
+        .map(|e| HtmlEdge {
+            source: e.source().index(),
+            target: e.target().index(),
+            declaration: e.weight().to_string(),
+        })
+        .collect();
+    let data = serde_json::to_string(&HtmlGraph { nodes, edges }).unwrap();
+    // Don't let paths close the `<script>` tag.
+    let data = data.replace("</", "<\\/");
+    VIEWER.replace(DATA_PLACEHOLDER, &data)
+}
+
+fn account(path: &Utf8Path) -> Option<String> {
+    let mut components = path.components();
+    match components.next()? {
+        Utf8Component::Normal("terraform") => Some("terraform".to_string()),
+        Utf8Component::Normal("terragrunt") => {
+            if components.next()? != Utf8Component::Normal("accounts") {
+                return None;
+            }
+            components.next().map(|account| account.to_string())
+        }
+        _ => None,
+    }
+}
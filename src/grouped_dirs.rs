@@ -1,184 +1,152 @@
-use std::collections::BTreeMap;
-
 use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
 
 use crate::{
-    aws,
+    auth,
     cmd_runner::{CmdRunner, PlanOutcome},
-    config::Config,
+    config::{AccountAuth, Config},
     dir::{self, current_dir_is_simpleinfra},
 };
 
-/// Directoried grouped by type and account
+/// Directories grouped by the credentials needed to run terraform on them.
 #[derive(Debug)]
 pub struct GroupedDirs {
-    /// Directories under the terraform directory
-    terraform: Vec<Utf8PathBuf>,
-    /// Directories under the terragrunt directory, grouped by account
-    terragrunt: BTreeMap<String, Vec<Utf8PathBuf>>,
+    /// Directories keep the order in which they were provided.
+    /// Accounts configured to be skipped are excluded.
+    groups: Vec<AuthGroup>,
+}
+
+/// Directories of the accounts that share the same authentication.
+#[derive(Debug)]
+pub struct AuthGroup {
+    /// Names of the accounts. The directories under `terraform` belong to the `legacy` account.
+    pub accounts: Vec<String>,
+    pub auth: AccountAuth,
+    pub dirs: Vec<Utf8PathBuf>,
 }
 
 impl GroupedDirs {
-    pub fn new<T>(directories: Vec<T>) -> Self
+    /// `directories` are expected to be sorted by dependencies.
+    /// A new group starts every time the authentication changes, so that the order is kept
+    /// across groups.
+    pub fn new<T>(directories: Vec<T>, config: &Config) -> Self
     where
         T: AsRef<Utf8Path>,
     {
         assert!(current_dir_is_simpleinfra());
-        let directories: Vec<&Utf8Path> = directories
-            .iter()
-            .map(|d| {
-                let dir = d.as_ref();
-                if dir.is_absolute() {
-                    dir.strip_prefix(dir::current_dir()).unwrap()
-                } else {
-                    dir
+        let mut groups: Vec<AuthGroup> = vec![];
+        for d in &directories {
+            let dir = d.as_ref();
+            let dir = if dir.is_absolute() {
+                dir.strip_prefix(dir::current_dir()).unwrap()
+            } else {
+                dir
+            };
+            let Some((account, auth)) = account_auth(dir, config) else {
+                continue;
+            };
+            if auth == AccountAuth::Skip {
+                println!("⏭️ Skipping account {account}: {dir}");
+                continue;
+            }
+            match groups.last_mut().filter(|g| g.auth == auth) {
+                Some(group) => {
+                    if !group.accounts.contains(&account) {
+                        group.accounts.push(account);
+                    }
+                    group.dirs.push(dir.to_path_buf());
                 }
-            })
-            .collect();
-
-        let terragrunt_dirs: Vec<&Utf8Path> =
-            get_dirs_starting_with(directories.clone(), "terragrunt");
-        let terraform_dirs: Vec<&Utf8Path> =
-            get_dirs_starting_with(directories.clone(), "terraform");
-        let grouped_terragrunt_dirs = group_terragrunt_dirs_by_account(terragrunt_dirs);
-        Self {
-            terraform: terraform_dirs
-                .into_iter()
-                .map(|d| d.to_path_buf())
-                .collect(),
-            terragrunt: grouped_terragrunt_dirs
-                .into_iter()
-                .map(|(k, v)| (k, v.into_iter().map(|d| d.to_path_buf()).collect()))
-                .collect(),
+                None => groups.push(AuthGroup {
+                    accounts: vec![account],
+                    auth,
+                    dirs: vec![dir.to_path_buf()],
+                }),
+            }
         }
+        Self { groups }
     }
 
-    pub fn contains_legacy_account(&self) -> bool {
-        self.terragrunt.contains_key("legacy") || !self.terraform.is_empty()
-    }
-
-    pub fn terraform_dirs(&self) -> Vec<&Utf8Path> {
-        self.terraform
-            .iter()
-            .map(|d| d.as_path())
-            .collect::<Vec<_>>()
+    pub fn groups(&self) -> &[AuthGroup] {
+        &self.groups
     }
 
-    pub fn legacy_terragrunt_dirs(&self) -> Vec<Utf8PathBuf> {
-        self.terragrunt.get("legacy").cloned().unwrap_or_default()
+    pub fn upgrade_all(&self, config: &Config) -> Vec<(Utf8PathBuf, PlanOutcome)> {
+        self.upgrade(config, None)
     }
 
-    /// Returns a map of account names to directories.
-    /// Legacy account is excluded.
-    pub fn sso_terragrunt_dirs(&self) -> BTreeMap<&str, Vec<Utf8PathBuf>> {
-        self.terragrunt
-            .iter()
-            .filter(|(account, _)| !account.starts_with("legacy"))
-            .map(|(account, dirs)| (account.as_str(), dirs.clone()))
-            .collect()
+    /// Upgrade only `provider` in the lockfiles.
+    pub fn upgrade_provider(&self, provider: &str, config: &Config) -> Vec<(Utf8PathBuf, PlanOutcome)> {
+        self.upgrade(config, Some(provider))
     }
 
-    pub fn upgrade_all(&self, config: &Config) -> Vec<(Utf8PathBuf, PlanOutcome)> {
-        let mut output: Vec<(Utf8PathBuf, PlanOutcome)> = vec![];
-        if self.contains_legacy_account() {
-            let legacy_tg_dirs = self.legacy_terragrunt_dirs();
-            let plan_outcome = upgrade_legacy_dirs(self.terraform_dirs(), legacy_tg_dirs, config);
-            output.extend(plan_outcome);
+    fn upgrade(&self, config: &Config, only_provider: Option<&str>) -> Vec<(Utf8PathBuf, PlanOutcome)> {
+        let mut outcome = vec![];
+        for group in &self.groups {
+            println!("ℹ️ Upgrading accounts {:?}", group.accounts);
+            let cmd_runner =
+                CmdRunner::new(|reuse_cache| auth::login(&group.auth, config, reuse_cache));
+            for d in &group.dirs {
+                let plan_outcome = if is_terraform_dir(d) {
+                    cmd_runner.terraform_upgrade(d, only_provider);
+                    cmd_runner.terraform_plan(d)
+                } else {
+                    cmd_runner.terragrunt_upgrade(d, only_provider);
+                    cmd_runner.terragrunt_plan(d)
+                };
+                outcome.push((d.clone(), plan_outcome));
+            }
         }
-
-        let sso_terragrunt_dirs = self.sso_terragrunt_dirs();
-        let plan_outcome = upgrade_terragrunt_with_sso(&sso_terragrunt_dirs);
-        output.extend(plan_outcome);
-        output
+        outcome
     }
 }
 
-fn get_dirs_starting_with<'a>(directories: Vec<&'a Utf8Path>, name: &str) -> Vec<&'a Utf8Path> {
-    directories
-        .into_iter()
-        .filter(|&d| is_root_dir(d, name))
-        .collect()
+/// Whether the directory is a terraform module, that isn't run with terragrunt.
+pub fn is_terraform_dir(dir: &Utf8Path) -> bool {
+    is_root_dir(dir, "terraform")
 }
 
 fn is_root_dir(dir: &Utf8Path, name: &str) -> bool {
     dir.components().next() == Some(Utf8Component::Normal(name))
 }
 
-fn group_terragrunt_dirs_by_account(
-    terragrunt_dirs: Vec<&Utf8Path>,
-) -> BTreeMap<String, Vec<&Utf8Path>> {
-    let mut dirs = BTreeMap::new();
-    for d in terragrunt_dirs {
-        let mut components = d.components();
-        let terragrunt_dir = components.next().unwrap();
-        assert_eq!(terragrunt_dir, Utf8Component::Normal("terragrunt"));
-        let accounts_dir = components.next().unwrap();
-        assert_eq!(accounts_dir, Utf8Component::Normal("accounts"));
-        let account = components.next().unwrap();
-        // Add the directory to the account's list of directories.
-        // If the account does not exist, create a new list with the directory.
-        // If the account exists, append the directory to the list.
-        dirs.entry(account.to_string())
-            .or_insert_with(Vec::new)
-            .push(d);
-    }
-    dirs
-}
-
-fn upgrade_legacy_dirs<T, U>(
-    terraform_dirs: Vec<T>,
-    terragrunt_dirs: Vec<U>,
-    config: &Config,
-) -> Vec<(Utf8PathBuf, PlanOutcome)>
-where
-    T: AsRef<Utf8Path>,
-    U: AsRef<Utf8Path>,
-{
-    let terraform_dirs = terraform_dirs
-        .iter()
-        .map(|d| d.as_ref())
-        .collect::<Vec<_>>();
-    let terragrunt_dirs = terragrunt_dirs
-        .iter()
-        .map(|d| d.as_ref())
-        .collect::<Vec<_>>();
-    // logout before login, to avoid issues with multiple profiles
-    aws::sso_logout();
-    let login_env_vars = aws::legacy_login(config.op_legacy_item_id.as_deref());
-    let cmd_runner = CmdRunner::new(login_env_vars);
-
-    let mut outcome = vec![];
-    for d in terraform_dirs {
-        cmd_runner.terraform_init_upgrade(d);
-        let plan_outcome = cmd_runner.terraform_plan(d);
-        outcome.push((d.to_path_buf(), plan_outcome));
+/// Name and authentication of the account of a directory under `terraform` or
+/// `terragrunt/accounts`.
+/// The directories under `terraform` belong to the `legacy` account and always use
+/// [`AccountAuth::Legacy`]: the `accounts` config only applies to `terragrunt/accounts`.
+fn account_auth(dir: &Utf8Path, config: &Config) -> Option<(String, AccountAuth)> {
+    if is_terraform_dir(dir) {
+        return Some(("legacy".to_string(), AccountAuth::Legacy));
     }
-    for d in terragrunt_dirs {
-        cmd_runner.terragrunt_init_upgrade(d);
-        let plan_outcome = cmd_runner.terragrunt_plan(d);
-        outcome.push((d.to_path_buf(), plan_outcome));
+    if !is_root_dir(dir, "terragrunt") {
+        return None;
     }
-    outcome
+    let mut components = dir.components().skip(1);
+    let accounts_dir = components.next().unwrap();
+    assert_eq!(accounts_dir, Utf8Component::Normal("accounts"));
+    let account = components.next().unwrap().to_string();
+    let auth = config.account_auth(&account);
+    Some((account, auth))
 }
 
-fn upgrade_terragrunt_with_sso<T>(terragrunt_sso_dirs: &BTreeMap<&str, Vec<T>>) -> Vec<(Utf8PathBuf, PlanOutcome)>
-where
-    T: AsRef<Utf8Path>,
-{
-    let mut outcome = vec![];
-    let terragrunt_sso_dirs = terragrunt_sso_dirs
-        .iter()
-        .map(|(k, v)| (*k, v.iter().map(|d| d.as_ref()).collect::<Vec<_>>()))
-        .collect::<BTreeMap<_, _>>();
-    for (account, dirs) in terragrunt_sso_dirs {
-        aws::sso_logout();
-        aws::sso_login(account);
-        let cmd_runner =CmdRunner::new(BTreeMap::new());
-        for d in dirs {
-            cmd_runner.terragrunt_init_upgrade(d);
-            let plan_outcome = cmd_runner.terragrunt_plan(d);
-            outcome.push( (d.to_path_buf(), plan_outcome));
-        }
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn terraform_dirs_always_use_legacy_auth() {
+        let config = Config {
+            accounts: BTreeMap::from([("legacy".to_string(), AccountAuth::Skip)]),
+            ..Config::default()
+        };
+        assert_eq!(
+            account_auth(Utf8Path::new("terraform/bastion"), &config),
+            Some(("legacy".to_string(), AccountAuth::Legacy))
+        );
+        assert_eq!(
+            account_auth(Utf8Path::new("terragrunt/accounts/legacy/app"), &config),
+            Some(("legacy".to_string(), AccountAuth::Skip))
+        );
+        assert_eq!(account_auth(Utf8Path::new("README.md"), &config), None);
     }
-    outcome
 }
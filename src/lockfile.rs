@@ -0,0 +1,93 @@
+/// Prefix of the provider names in the lockfile.
+const REGISTRY: &str = "registry.terraform.io/";
+
+/// Take the `upgraded` lockfile and restore the `original` version of every
+/// provider except `provider` (e.g. `hashicorp/aws`).
+pub fn keep_only_provider_upgrade(original: &str, upgraded: &str, provider: &str) -> String {
+    let original_blocks = provider_blocks(original);
+    let mut output = String::new();
+    let mut lines = upgraded.lines();
+    while let Some(line) = lines.next() {
+        let Some(name) = provider_name(line) else {
+            output.push_str(line);
+            output.push('\n');
+            continue;
+        };
+        let block = read_block(line, &mut lines);
+        let original_block = original_blocks
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, b)| b);
+        match original_block {
+            Some(original_block) if name != provider => output.push_str(original_block),
+            _ => output.push_str(&block),
+        }
+    }
+    output
+}
+
+/// Provider name -> text of the provider block.
+fn provider_blocks(content: &str) -> Vec<(&str, String)> {
+    let mut blocks = vec![];
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        if let Some(name) = provider_name(line) {
+            blocks.push((name, read_block(line, &mut lines)));
+        }
+    }
+    blocks
+}
+
+/// E.g. `provider "registry.terraform.io/hashicorp/aws" {` -> `hashicorp/aws`.
+fn provider_name(line: &str) -> Option<&str> {
+    let name = line.strip_prefix("provider \"")?.split('"').next()?;
+    Some(name.strip_prefix(REGISTRY).unwrap_or(name))
+}
+
+/// Read the lines of a block until its closing brace, that is at the start of a line.
+fn read_block<'a>(first_line: &str, lines: &mut impl Iterator<Item = &'a str>) -> String {
+    let mut block = format!("{first_line}\n");
+    for line in lines.by_ref() {
+        block.push_str(line);
+        block.push('\n');
+        if line.starts_with('}') {
+            break;
+        }
+    }
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lockfile(aws_version: &str, google_version: &str) -> String {
+        format!(
+            r#"# This file is maintained automatically by "terraform init".
+
+provider "registry.terraform.io/hashicorp/aws" {{
+  version     = "{aws_version}"
+  constraints = "~> 5.0"
+  hashes = [
+    "h1:aws-{aws_version}",
+  ]
+}}
+
+provider "registry.terraform.io/hashicorp/google" {{
+  version = "{google_version}"
+  hashes = [
+    "h1:google-{google_version}",
+  ]
+}}
+"#
+        )
+    }
+
+    #[test]
+    fn only_selected_provider_is_upgraded() {
+        let original = lockfile("5.1.0", "4.0.0");
+        let upgraded = lockfile("5.2.0", "4.1.0");
+        let lockfile_content = keep_only_provider_upgrade(&original, &upgraded, "hashicorp/aws");
+        assert_eq!(lockfile_content, lockfile("5.2.0", "4.0.0"));
+    }
+}
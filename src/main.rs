@@ -1,5 +1,7 @@
 mod args;
+mod auth;
 mod aws;
+mod aws_config;
 mod clipboard;
 mod cmd;
 mod cmd_runner;
@@ -7,13 +9,20 @@ mod command;
 mod config;
 mod dir;
 mod git;
+mod github;
 mod grouped_dirs;
+mod lockfile;
 mod log;
+mod pr_comment;
 mod provider;
+mod secret;
 mod select;
+mod sso_cache;
+mod sts;
 mod pretty_format;
 mod graph;
-mod envirnoment;
+mod graph_cache;
+mod graph_html;
 
 use args::CliArgs;
 use clap::Parser as _;
@@ -27,12 +36,19 @@ async fn main() {
     let config = config::parse_config().unwrap();
     match args.command {
         args::Command::Upgrade(args) => command::upgrade::upgrade(args, &config),
-        args::Command::PlanPr(args) => command::plan_pr::plan_pr(args, &config),
-        args::Command::UpgradeProvider => {
-            command::upgrade_provider::upgrade_provider(&config).await
+        args::Command::PlanPr(args) => command::plan_pr::plan_pr(args, &config).await,
+        args::Command::PlanBranch(args) => command::plan_branch::plan_branch(args, &config),
+        args::Command::UpgradeProvider(args) => {
+            command::upgrade_provider::upgrade_provider(args, &config).await
         }
         args::Command::Config => command::config_cmd::create_default_config(),
-        args::Command::LegacyLogin => command::legacy_login::login_to_legacy_aws_account(&config),
+        args::Command::LegacyLogin(args) => {
+            command::legacy_login::login_to_legacy_aws_account(args, &config)
+        }
         args::Command::Graph(args) => command::graph_cmd::print_graph(args).await,
+        args::Command::Why(args) => command::why::why(args),
+        args::Command::Lint(args) => command::lint::lint(args),
+        args::Command::Order(args) => command::order::print_order(args),
+        args::Command::Exec(args) => command::exec::exec(args, &config),
     }
 }
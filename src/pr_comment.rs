@@ -0,0 +1,171 @@
+use crate::{github::GitHubClient, pretty_format};
+
+/// Hidden marker used to find the comments created by infratk.
+const MARKER: &str = "<!-- infratk-plan-summary";
+/// GitHub rejects comments longer than 65536 characters.
+/// Leave some room for the marker and the closing code fences.
+const MAX_COMMENT_LEN: usize = 65_000;
+const CODE_FENCE: &str = "```";
+
+/// Post the plan summary to the PR.
+/// Comments posted by previous runs of the same user are edited in place.
+/// If the summary is too long, it's split across multiple comments.
+pub async fn upsert_plan_comment(
+    github: &GitHubClient,
+    pr_number: u64,
+    output_str: &str,
+) -> anyhow::Result<()> {
+    let body = pretty_format::strip_ansi_escapes(output_str);
+    let parts = split_comment(&body, MAX_COMMENT_LEN);
+    // Comments of other users can't be edited.
+    let login = github.current_user().await?.login;
+    // Comments are listed from the oldest to the newest.
+    let existing_comments: Vec<u64> = github
+        .list_comments(pr_number)
+        .await?
+        .into_iter()
+        .filter(|c| c.body.starts_with(MARKER))
+        .filter(|c| c.user.as_ref().is_some_and(|u| u.login == login))
+        .map(|c| c.id)
+        .collect();
+    let parts_count = parts.len();
+    for (i, part) in parts.into_iter().enumerate() {
+        let body = format!("{MARKER} part {}/{parts_count} -->\n{part}", i + 1);
+        match existing_comments.get(i) {
+            Some(comment_id) => github.update_comment(*comment_id, &body).await?,
+            None => github.create_comment(pr_number, &body).await?,
+        }
+    }
+    // The previous summary was longer: delete the comments that aren't needed anymore.
+    for comment_id in existing_comments.iter().skip(parts_count) {
+        github.delete_comment(*comment_id).await?;
+    }
+    println!("✅ Plan summary posted to PR #{pr_number}");
+    Ok(())
+}
+
+/// Split the comment in parts that are at most `max_len` bytes long, at line boundaries.
+/// Code blocks that are split are closed at the end of a part and reopened in the next one.
+/// Parts that would only contain code fences are skipped.
+fn split_comment(body: &str, max_len: usize) -> Vec<String> {
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut in_code_block = false;
+    // Room needed to close the code block at the end of a part.
+    let closing_len = CODE_FENCE.len() + 1;
+    for line in body.lines().flat_map(|l| split_long_line(l, max_len - 2 * closing_len)) {
+        if current.len() + line.len() + 1 + closing_len > max_len {
+            if in_code_block {
+                current.push_str(CODE_FENCE);
+                current.push('\n');
+            }
+            let part = std::mem::take(&mut current);
+            if has_content(&part) {
+                parts.push(part);
+            }
+            if in_code_block {
+                current.push_str(CODE_FENCE);
+                current.push('\n');
+            }
+        }
+        if line.starts_with(CODE_FENCE) {
+            in_code_block = !in_code_block;
+        }
+        current.push_str(line);
+        current.push('\n');
+    }
+    if has_content(&current) || parts.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+/// Whether the part contains something other than code fences and blank lines.
+fn has_content(part: &str) -> bool {
+    part.lines()
+        .any(|line| !line.trim().is_empty() && line.trim() != CODE_FENCE)
+}
+
+/// Split lines longer than `max_len` bytes, without breaking UTF-8 characters.
+fn split_long_line(line: &str, max_len: usize) -> Vec<&str> {
+    let mut chunks = vec![];
+    let mut rest = line;
+    while rest.len() > max_len {
+        let mut end = max_len;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, remaining) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = remaining;
+    }
+    chunks.push(rest);
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretString;
+
+    use super::*;
+    use crate::github::tests::mock_server;
+
+    #[test]
+    fn short_comment_is_not_split() {
+        let body = "## Plan summary\n✅ module1\n";
+        assert_eq!(split_comment(body, 100), vec![body.to_string()]);
+    }
+
+    #[test]
+    fn code_blocks_are_reopened_after_split() {
+        let body = "👉 module1:\n```\nline1\nline2\nline3\n```\n";
+        let parts = split_comment(body, 25);
+        assert!(parts.len() > 1);
+        for part in &parts {
+            assert!(part.len() <= 25, "part too long: {part:?}");
+            assert_eq!(part.matches(CODE_FENCE).count() % 2, 0, "{part:?}");
+        }
+        let lines: String = parts.concat();
+        for line in ["line1", "line2", "line3"] {
+            assert!(lines.contains(line));
+        }
+    }
+
+    #[test]
+    fn parts_with_only_fences_are_skipped() {
+        let body = "```
+line1
+```
+end
+";
+        // The closing fence doesn't fit in the first part.
+        let parts = split_comment(body, 14);
+        assert_eq!(parts, vec!["```
+line1
+```
+", "end
+"]);
+    }
+
+    #[tokio::test]
+    async fn only_own_comments_are_edited() {
+        let comments = format!(
+            r#"[
+                {{"id": 1, "body": "{MARKER} part 1/1 -->", "user": {{"login": "colleague"}}}},
+                {{"id": 2, "body": "{MARKER} part 1/1 -->", "user": {{"login": "me"}}}}
+            ]"#
+        );
+        let url = mock_server(vec![
+            ("/user ", r#"{"login": "me"}"#.to_string()),
+            ("/repos/rust-lang/simpleinfra/issues/42/comments?", comments),
+            // The mock server answers 404 if the comment of the colleague is edited instead.
+            ("/repos/rust-lang/simpleinfra/issues/comments/2 ", "{}".to_string()),
+        ]);
+        let client = GitHubClient::with_api_url(
+            url,
+            SecretString::new("token".to_string()),
+            "rust-lang/simpleinfra".to_string(),
+        );
+        upsert_plan_comment(&client, 42, "✅ module1").await.unwrap();
+    }
+}
@@ -1,31 +1,62 @@
 use camino::Utf8PathBuf;
+use regex::Regex;
 
-use crate::cmd_runner::PlanOutcome;
+use crate::cmd_runner::{PlanOutcome, PlanReason};
 
 /// Print two lists of directories, one for each outcome
 pub fn format_output(output: Vec<(Utf8PathBuf, PlanOutcome)>) -> String {
+    let output = output
+        .into_iter()
+        .map(|(dir, outcome)| (dir, outcome, None))
+        .collect();
+    format(output)
+}
+
+/// Like [`format_output`], but also show why every directory was planned.
+pub fn format_output_with_reasons(output: Vec<(Utf8PathBuf, PlanOutcome, PlanReason)>) -> String {
+    let output = output
+        .into_iter()
+        .map(|(dir, outcome, reason)| (dir, outcome, Some(reason)))
+        .collect();
+    format(output)
+}
+
+fn format(output: Vec<(Utf8PathBuf, PlanOutcome, Option<PlanReason>)>) -> String {
     let mut output_str = String::from("## 📃📃 Plan summary 📃📃\n");
+    let (auth_failed, output): (Vec<_>, Vec<_>) = output
+        .into_iter()
+        .partition(|(_, o, _)| matches!(o, PlanOutcome::AuthFailed(_)));
+    let (removed, output): (Vec<_>, Vec<_>) = output
+        .into_iter()
+        .partition(|(_, _, r)| *r == Some(PlanReason::Removed));
     let (no_changes, changes): (Vec<_>, Vec<_>) = output
         .into_iter()
-        .partition(|(_, o)| matches!(o, PlanOutcome::NoChanges));
+        .partition(|(_, o, _)| matches!(o, PlanOutcome::NoChanges));
     if !no_changes.is_empty() {
         output_str.push_str("\nNo changes detected (apply not needed):\n");
     }
-    for (dir, _) in no_changes {
-        output_str.push_str(&format!("✅ {}\n", dir));
+    for (dir, _, reason) in no_changes {
+        output_str.push_str(&format!("✅ {}{}\n", dir, format_reason(reason)));
     }
 
     if !changes.is_empty() {
         output_str.push_str("\nChanges detected (apply needed):\n");
     }
-    for (dir, _) in &changes {
-        output_str.push_str(&format!("❌ {}\n", dir));
+    for (dir, _, reason) in &changes {
+        output_str.push_str(&format!("❌ {}{}\n", dir, format_reason(*reason)));
+    }
+
+    if !auth_failed.is_empty() {
+        output_str.push_str("\nAuthentication failed (plan again after logging in):\n");
+    }
+    for (dir, _, reason) in &auth_failed {
+        output_str.push_str(&format!("🔒 {}{}\n", dir, format_reason(*reason)));
     }
 
     if !changes.is_empty() {
         output_str.push_str("\n## 📃📃 Plan output 📃📃\n");
     }
-    for (dir, output) in &changes {
+    for (dir, output, _) in &changes {
         output_str.push_str(&format!("👉 {}:\n", dir));
         if let PlanOutcome::Changes(output) = output {
             output_str.push_str(&format!("\n```\n{}\n```\n", output));
@@ -34,5 +65,53 @@ pub fn format_output(output: Vec<(Utf8PathBuf, PlanOutcome)>) -> String {
         }
     }
 
+    if !removed.is_empty() {
+        output_str.push_str("\n## 🗑️ Stacks being removed 🗑️\n");
+    }
+    for (dir, output, _) in &removed {
+        match output {
+            PlanOutcome::NoChanges => {
+                output_str.push_str(&format!("🗑️ {}: no resources to destroy\n", dir))
+            }
+            PlanOutcome::Changes(output) => {
+                output_str.push_str(&format!("🗑️ {}:\n\n```\n{}\n```\n", dir, output))
+            }
+            PlanOutcome::AuthFailed(_) => panic!("Expected a destroy plan, got an auth failure"),
+        }
+    }
+
     output_str
 }
+
+fn format_reason(reason: Option<PlanReason>) -> &'static str {
+    match reason {
+        Some(PlanReason::Changed) => " (changed)",
+        Some(PlanReason::Dependent) => " (dependent)",
+        Some(PlanReason::Removed) => " (removed)",
+        None => "",
+    }
+}
+
+/// Strip ANSI escape sequences, e.g. the colors of the terraform output.
+pub fn strip_ansi_escapes(output_str: &str) -> String {
+    let re = Regex::new(r"\x1b\[([\x30-\x3f]*[\x20-\x2f]*[\x40-\x7e])").unwrap();
+    re.replace_all(output_str, "").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_stacks_with_auth_failures_are_not_shown_as_destroy_plans() {
+        let output = vec![(
+            Utf8PathBuf::from("terragrunt/accounts/dev/app"),
+            PlanOutcome::AuthFailed("ExpiredToken".to_string()),
+            PlanReason::Removed,
+        )];
+        let output_str = format_output_with_reasons(output);
+        assert!(output_str.contains("Authentication failed"));
+        assert!(output_str.contains("🔒 terragrunt/accounts/dev/app (removed)"));
+        assert!(!output_str.contains("Stacks being removed"));
+    }
+}
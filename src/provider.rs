@@ -39,6 +39,7 @@ pub async fn outdated_providers(providers: Providers) -> anyhow::Result<Provider
                 provider_name,
                 ProviderVersions {
                     versions: outdated_versions,
+                    latest_version: Some(latest_version),
                 },
             );
         }
@@ -96,6 +97,7 @@ pub fn get_all_providers(lockfiles: &[Utf8PathBuf]) -> Providers {
                         .entry(provider_name)
                         .or_insert_with(|| ProviderVersions {
                             versions: BTreeMap::new(),
+                            latest_version: None,
                         })
                         .versions
                         .entry(version)
@@ -0,0 +1,205 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use hmac::{Hmac, Mac as _};
+use secrecy::{ExposeSecret as _, SecretString};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+use crate::cmd::Cmd;
+
+/// Something that can return a secret, e.g. an MFA code.
+pub trait SecretBackend {
+    fn get(&self) -> anyhow::Result<SecretString>;
+}
+
+/// Where to read a secret from, as written in `config.toml`.
+///
+/// E.g.:
+/// ```toml
+/// [legacy_mfa]
+/// backend = "totp"
+/// user = "legacy"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum SecretSource {
+    /// 1Password CLI.
+    #[serde(rename = "1password")]
+    OnePassword {
+        item_id: String,
+        /// Field of the item. If missing, the one-time password of the item is returned.
+        field: Option<String>,
+    },
+    /// The `pass` password manager.
+    Pass {
+        name: String,
+        /// If true, return the one-time password of the entry
+        /// with the `pass-otp` extension.
+        #[serde(default)]
+        otp: bool,
+    },
+    /// Environment variable.
+    Env { var: String },
+    /// Ask the user.
+    Prompt { message: Option<String> },
+    /// Generate a one-time password from a base32 seed stored in the OS keyring.
+    /// Store the seed with e.g. `secret-tool store --label=infratk service infratk username <user>`
+    /// in the Secret Service on Linux (e.g. GNOME Keyring or KWallet)
+    /// or `security add-generic-password -s infratk -a <user> -w` on macOS.
+    Totp {
+        #[serde(default = "default_keyring_service")]
+        service: String,
+        user: String,
+    },
+}
+
+fn default_keyring_service() -> String {
+    "infratk".to_string()
+}
+
+impl SecretSource {
+    pub fn backend(&self) -> Box<dyn SecretBackend> {
+        match self.clone() {
+            Self::OnePassword { item_id, field } => Box::new(OnePassword { item_id, field }),
+            Self::Pass { name, otp } => Box::new(Pass { name, otp }),
+            Self::Env { var } => Box::new(EnvVar { var }),
+            Self::Prompt { message } => Box::new(Prompt {
+                message: message.unwrap_or_else(|| "Secret:".to_string()),
+            }),
+            Self::Totp { service, user } => Box::new(KeyringTotp { service, user }),
+        }
+    }
+}
+
+struct OnePassword {
+    item_id: String,
+    field: Option<String>,
+}
+
+impl SecretBackend for OnePassword {
+    fn get(&self) -> anyhow::Result<SecretString> {
+        let mut args = vec!["item", "get", &self.item_id];
+        match &self.field {
+            Some(field) => args.extend(["--fields", field, "--reveal"]),
+            None => args.push("--otp"),
+        }
+        command_output("op", &args)
+    }
+}
+
+struct Pass {
+    name: String,
+    otp: bool,
+}
+
+impl SecretBackend for Pass {
+    fn get(&self) -> anyhow::Result<SecretString> {
+        let output = if self.otp {
+            command_output("pass", &["otp", &self.name])?
+        } else {
+            command_output("pass", &["show", &self.name])?
+        };
+        // The password is the first line of the entry.
+        let secret = output.expose_secret().lines().next().unwrap_or_default();
+        Ok(secret.to_string().into())
+    }
+}
+
+struct EnvVar {
+    var: String,
+}
+
+impl SecretBackend for EnvVar {
+    fn get(&self) -> anyhow::Result<SecretString> {
+        let value = std::env::var(&self.var)
+            .with_context(|| format!("environment variable {} isn't set", self.var))?;
+        Ok(value.into())
+    }
+}
+
+struct Prompt {
+    message: String,
+}
+
+impl SecretBackend for Prompt {
+    fn get(&self) -> anyhow::Result<SecretString> {
+        let value = inquire::Password::new(&self.message)
+            .without_confirmation()
+            .prompt()?;
+        Ok(value.into())
+    }
+}
+
+struct KeyringTotp {
+    service: String,
+    user: String,
+}
+
+impl SecretBackend for KeyringTotp {
+    fn get(&self) -> anyhow::Result<SecretString> {
+        let seed = keyring::Entry::new(&self.service, &self.user)?
+            .get_password()
+            .with_context(|| {
+                format!(
+                    "can't read the TOTP seed of service {} and user {} from the keyring",
+                    self.service, self.user
+                )
+            })?;
+        let seed = decode_seed(&seed)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        Ok(totp(&seed, now).into())
+    }
+}
+
+/// Run the command and return its trimmed stdout.
+fn command_output(cmd: &str, args: &[&str]) -> anyhow::Result<SecretString> {
+    let output = Cmd::new(cmd, args).hide_stdout().run();
+    anyhow::ensure!(
+        output.status().success(),
+        "`{cmd}` failed: {}",
+        output.stderr()
+    );
+    Ok(output.stdout().to_string().into())
+}
+
+/// Decode a base32 seed, as shown by the websites when configuring an MFA device.
+fn decode_seed(seed: &str) -> anyhow::Result<Vec<u8>> {
+    let seed: String = seed
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    data_encoding::BASE32_NOPAD
+        .decode(seed.as_bytes())
+        .context("the TOTP seed isn't valid base32")
+}
+
+/// One-time password of [RFC 6238](https://www.rfc-editor.org/rfc/rfc6238),
+/// with the parameters used by AWS: SHA-1, 30 seconds steps and 6 digits.
+fn totp(seed: &[u8], unix_time: u64) -> String {
+    let counter = unix_time / 30;
+    let mut mac = Hmac::<Sha1>::new_from_slice(seed).expect("HMAC accepts keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    // Dynamic truncation of RFC 4226.
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let code = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    format!("{:06}", code % 1_000_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totp_matches_rfc_test_vectors() {
+        // Seed of the RFC test vectors for SHA-1, in base32.
+        let seed = decode_seed("GEZDGNBVGY3TQOJQ GEZDGNBVGY3TQOJQ").unwrap();
+        assert_eq!(seed, b"12345678901234567890");
+        // The RFC shows 8 digits, AWS uses the last 6.
+        assert_eq!(totp(&seed, 59), "287082");
+        assert_eq!(totp(&seed, 1111111109), "081804");
+        assert_eq!(totp(&seed, 2000000000), "279037");
+    }
+}
@@ -0,0 +1,103 @@
+use camino::Utf8Path;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::aws_config::{self, aws_dir, section_value};
+
+/// Token written by `aws sso login` under `~/.aws/sso/cache`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedToken {
+    start_url: Option<String>,
+    expires_at: Option<String>,
+}
+
+/// When the SSO session of `profile` expires,
+/// or `None` if `aws sso login` wasn't done for the profile.
+pub fn session_expiration(profile: &str) -> Option<DateTime<Utc>> {
+    let aws_dir = aws_dir()?;
+    let config = fs_err::read_to_string(aws_dir.join("config")).ok()?;
+    let Some(start_url) = sso_start_url(&config, profile) else {
+        debug!("No sso_start_url found for profile {profile}");
+        return None;
+    };
+    token_expiration(&aws_dir.join("sso").join("cache"), &start_url)
+}
+
+/// Find the SSO start URL of `profile` in the content of `~/.aws/config`.
+/// The URL is either in the profile itself or in the `sso-session` section it references.
+fn sso_start_url(config: &str, profile: &str) -> Option<String> {
+    let profile_section = aws_config::profile_section(profile);
+    if let Some(url) = section_value(config, &profile_section, "sso_start_url") {
+        return Some(url);
+    }
+    let sso_session = section_value(config, &profile_section, "sso_session")?;
+    section_value(config, &format!("sso-session {sso_session}"), "sso_start_url")
+}
+
+/// Latest expiration of the tokens for `start_url` in `cache_dir`.
+fn token_expiration(cache_dir: &Utf8Path, start_url: &str) -> Option<DateTime<Utc>> {
+    cache_dir
+        .read_dir_utf8()
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().extension() == Some("json"))
+        .filter_map(|entry| fs_err::read_to_string(entry.path()).ok())
+        // The cache also contains files that aren't tokens, e.g. the registered client.
+        .filter_map(|content| serde_json::from_str::<CachedToken>(&content).ok())
+        .filter(|token| token.start_url.as_deref() == Some(start_url))
+        .filter_map(|token| token.expires_at.as_deref().and_then(parse_expiration))
+        .max()
+}
+
+/// The AWS CLI writes dates like `2024-05-01T12:00:00Z`.
+/// Older versions write `2024-05-01T12:00:00UTC`.
+fn parse_expiration(expires_at: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(expires_at) {
+        return Some(date.with_timezone(&Utc));
+    }
+    NaiveDateTime::parse_from_str(expires_at, "%Y-%m-%dT%H:%M:%SUTC")
+        .ok()
+        .map(|date| date.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::auth::Session;
+
+    #[test]
+    fn token_of_sso_session_is_found() {
+        let config = r#"
+[profile rust-root]
+sso_session = rust
+sso_account_id = 123456789012
+
+[sso-session rust]
+sso_start_url = https://rust.awsapps.com/start
+sso_region = us-east-1
+"#;
+        let start_url = sso_start_url(config, "rust-root").unwrap();
+        assert_eq!(start_url, "https://rust.awsapps.com/start");
+
+        let cache_dir = camino_tempfile::tempdir().unwrap();
+        let token = r#"{"startUrl": "https://rust.awsapps.com/start", "accessToken": "token", "expiresAt": "2024-05-01T12:00:00Z"}"#;
+        fs_err::write(cache_dir.path().join("token.json"), token).unwrap();
+        let session = Session {
+            env_vars: BTreeMap::new(),
+            expiration: token_expiration(cache_dir.path(), &start_url),
+        };
+        assert_eq!(session.expiration, parse_expiration("2024-05-01T12:00:00Z"));
+        let now = parse_expiration("2024-05-01T11:00:00Z").unwrap();
+        assert!(!session.expires_soon(now));
+        let almost_expired = parse_expiration("2024-05-01T11:55:00Z").unwrap();
+        assert!(session.expires_soon(almost_expired));
+        assert_eq!(
+            token_expiration(cache_dir.path(), "https://other.awsapps.com/start"),
+            None
+        );
+    }
+}
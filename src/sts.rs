@@ -0,0 +1,372 @@
+use std::{collections::BTreeMap, io::Write as _, path::PathBuf};
+
+use anyhow::Context as _;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac as _};
+use secrecy::{ExposeSecret as _, SecretString};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use tracing::warn;
+
+use crate::{auth::Session, dir};
+
+const SESSION_CACHE_FILE: &str = "legacy-session.json";
+
+/// Long-term credentials of an IAM user.
+pub struct AccessKey {
+    pub id: String,
+    pub secret: SecretString,
+}
+
+/// Temporary credentials returned by STS.
+pub struct SessionCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: SecretString,
+    pub session_token: SecretString,
+    pub expiration: DateTime<Utc>,
+}
+
+impl SessionCredentials {
+    /// Session with the environment variables used by the AWS CLI and by terraform.
+    pub fn session(&self) -> Session {
+        let env_vars = BTreeMap::from([
+            (
+                "AWS_ACCESS_KEY_ID".to_string(),
+                self.access_key_id.clone().into(),
+            ),
+            (
+                "AWS_SECRET_ACCESS_KEY".to_string(),
+                self.secret_access_key.clone(),
+            ),
+            ("AWS_SESSION_TOKEN".to_string(), self.session_token.clone()),
+        ]);
+        Session {
+            env_vars,
+            expiration: Some(self.expiration),
+        }
+    }
+}
+
+/// Client of the STS query API, signed with AWS Signature Version 4.
+pub struct StsClient {
+    endpoint: String,
+    region: String,
+    access_key: AccessKey,
+}
+
+impl StsClient {
+    pub fn new(endpoint: String, region: String, access_key: AccessKey) -> Self {
+        Self {
+            endpoint,
+            region,
+            access_key,
+        }
+    }
+
+    pub fn get_session_token(
+        &self,
+        mfa_serial: &str,
+        token_code: &SecretString,
+        duration_seconds: u32,
+    ) -> anyhow::Result<SessionCredentials> {
+        let body = form_body(&[
+            ("Action", "GetSessionToken"),
+            ("Version", "2011-06-15"),
+            ("DurationSeconds", &duration_seconds.to_string()),
+            ("SerialNumber", mfa_serial),
+            ("TokenCode", token_code.expose_secret()),
+        ]);
+        let url = reqwest::Url::parse(&self.endpoint)
+            .with_context(|| format!("invalid STS endpoint {}", self.endpoint))?;
+        let host = url.host_str().context("STS endpoint without host")?;
+        let host = match url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        };
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = self.authorization(&host, url.path(), &amz_date, &body);
+
+        // The blocking client can't be used in the async runtime, so use a separate thread.
+        let (status, response) = std::thread::scope(|s| {
+            s.spawn(|| -> anyhow::Result<_> {
+                let response = reqwest::blocking::Client::new()
+                    .post(url.clone())
+                    .header("Content-Type", "application/x-www-form-urlencoded")
+                    .header("X-Amz-Date", &amz_date)
+                    .header("Authorization", authorization)
+                    .body(body)
+                    .send()?;
+                Ok((response.status(), response.text()?))
+            })
+            .join()
+            .unwrap()
+        })
+        .context("failed to call STS")?;
+        anyhow::ensure!(
+            status.is_success(),
+            "STS returned {status}: {}",
+            xml_value(&response, "Message").unwrap_or(&response)
+        );
+        parse_credentials(&response)
+    }
+
+    /// Value of the `Authorization` header, signing the `host` and `x-amz-date` headers.
+    fn authorization(&self, host: &str, path: &str, amz_date: &str, body: &str) -> String {
+        let date = &amz_date[..8];
+        let scope = format!("{date}/{}/sts/aws4_request", self.region);
+        let signed_headers = "host;x-amz-date";
+        let canonical_request = format!(
+            "POST\n{path}\n\nhost:{host}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{}",
+            hex(&Sha256::digest(body))
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request))
+        );
+        let signing_key = signing_key(
+            self.access_key.secret.expose_secret(),
+            date,
+            &self.region,
+            "sts",
+        );
+        let signature = hex(&hmac_sha256(&signing_key, &string_to_sign));
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.access_key.id
+        )
+    }
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{secret}").as_bytes(), date);
+    let key = hmac_sha256(&key, region);
+    let key = hmac_sha256(&key, service);
+    hmac_sha256(&key, "aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Encode the parameters as `application/x-www-form-urlencoded`.
+fn form_body(params: &[(&str, &str)]) -> String {
+    params
+        .iter()
+        .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn parse_credentials(response: &str) -> anyhow::Result<SessionCredentials> {
+    let value = |tag| {
+        xml_value(response, tag).with_context(|| format!("{tag} missing in STS response"))
+    };
+    let expiration = DateTime::parse_from_rfc3339(value("Expiration")?)
+        .context("invalid expiration in STS response")?
+        .with_timezone(&Utc);
+    Ok(SessionCredentials {
+        access_key_id: value("AccessKeyId")?.to_string(),
+        secret_access_key: value("SecretAccessKey")?.to_string().into(),
+        session_token: value("SessionToken")?.to_string().into(),
+        expiration,
+    })
+}
+
+/// Text of the first `<tag>` of the XML document.
+fn xml_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    Some(xml[start..end].trim())
+}
+
+/// Session credentials stored on disk, so that the MFA code is asked only when they expire.
+#[derive(Serialize, Deserialize)]
+struct CachedSession {
+    /// Profile of the long-term credentials used to get the session.
+    profile: String,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    expiration: DateTime<Utc>,
+}
+
+/// Session credentials obtained with the long-term credentials of `profile`,
+/// if they don't expire soon.
+pub fn load_cached_session(profile: &str) -> Option<SessionCredentials> {
+    let content = fs_err::read_to_string(session_cache_file()).ok()?;
+    let cached: CachedSession = serde_json::from_str(&content).ok()?;
+    if cached.profile != profile {
+        return None;
+    }
+    let credentials = SessionCredentials {
+        access_key_id: cached.access_key_id,
+        secret_access_key: cached.secret_access_key.into(),
+        session_token: cached.session_token.into(),
+        expiration: cached.expiration,
+    };
+    (!credentials.session().expires_soon(Utc::now())).then_some(credentials)
+}
+
+pub fn save_session(profile: &str, credentials: &SessionCredentials) {
+    let cached = CachedSession {
+        profile: profile.to_string(),
+        access_key_id: credentials.access_key_id.clone(),
+        secret_access_key: credentials.secret_access_key.expose_secret().clone(),
+        session_token: credentials.session_token.expose_secret().clone(),
+        expiration: credentials.expiration,
+    };
+    let content = serde_json::to_string(&cached).unwrap();
+    if let Err(err) = write_private_file(session_cache_file(), &content) {
+        warn!("Could not save the session of the legacy account: {err:?}");
+    }
+}
+
+fn session_cache_file() -> PathBuf {
+    dir::cache_dir().join(SESSION_CACHE_FILE)
+}
+
+/// Write a file that only the current user can read.
+fn write_private_file(path: PathBuf, content: &str) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // The mode is only applied when the file is created.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead as _, BufReader, Read as _},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::*;
+
+    #[test]
+    fn signing_key_matches_aws_example() {
+        // Example of the AWS documentation.
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn existing_cache_file_becomes_private() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = camino_tempfile::tempdir().unwrap();
+        let path = dir.path().join(SESSION_CACHE_FILE);
+        fs_err::write(&path, "old").unwrap();
+        fs_err::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_private_file(path.clone().into(), "new").unwrap();
+        let mode = fs_err::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs_err::read_to_string(&path).unwrap(), "new");
+    }
+
+    #[test]
+    fn session_token_is_requested_with_mfa() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = String::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                headers.push_str(&line);
+                line.clear();
+            }
+            let content_length: usize = headers
+                .lines()
+                .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                .unwrap();
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let response = r#"<GetSessionTokenResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <GetSessionTokenResult>
+    <Credentials>
+      <SessionToken>session-token</SessionToken>
+      <SecretAccessKey>secret-key</SecretAccessKey>
+      <Expiration>2024-05-01T12:00:00Z</Expiration>
+      <AccessKeyId>ASIAEXAMPLE</AccessKeyId>
+    </Credentials>
+  </GetSessionTokenResult>
+</GetSessionTokenResponse>"#;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                response.len()
+            )
+            .unwrap();
+            (headers, String::from_utf8(body).unwrap())
+        });
+
+        let client = StsClient::new(
+            endpoint,
+            "us-east-1".to_string(),
+            AccessKey {
+                id: "AKIAEXAMPLE".to_string(),
+                secret: SecretString::new("secret".to_string()),
+            },
+        );
+        let credentials = client
+            .get_session_token(
+                "arn:aws:iam::123456789012:mfa/user",
+                &SecretString::new("123456".to_string()),
+                3600,
+            )
+            .unwrap();
+        assert_eq!(credentials.access_key_id, "ASIAEXAMPLE");
+        assert_eq!(credentials.session_token.expose_secret(), "session-token");
+        assert_eq!(
+            credentials.expiration,
+            DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap()
+        );
+
+        let (headers, body) = server.join().unwrap();
+        assert_eq!(
+            body,
+            "Action=GetSessionToken&Version=2011-06-15&DurationSeconds=3600\
+             &SerialNumber=arn%3Aaws%3Aiam%3A%3A123456789012%3Amfa%2Fuser&TokenCode=123456"
+        );
+        assert!(headers
+            .to_lowercase()
+            .contains("authorization: aws4-hmac-sha256 credential=akiaexample/"));
+    }
+}